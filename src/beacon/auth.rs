@@ -0,0 +1,157 @@
+use std::{fmt::Debug, io, path::Path};
+
+use hmac::{Hmac, Mac};
+use serde_cbor::Value;
+use sha2::Sha256;
+
+use crate::util::{decode_hex, parse_lines};
+use super::{Beacon, BeaconError, Service};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Private use service tag carrying the MAC of a beacon
+pub const MAC_SERVICE_TAG: u8 = 128;
+
+/// Shortest shared key accepted, in bytes
+pub const MIN_KEY_SIZE: usize = 16;
+
+/// Shared keys authenticating beacons with HMAC-SHA256
+/// Beacons are signed with the first key and accepted with any of them, so keys can be rolled over
+#[derive(Clone)]
+pub struct SharedKeys {
+    keys: Vec<Vec<u8>>
+}
+
+impl Debug for SharedKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedKeys({} keys)", self.keys.len())
+    }
+}
+
+impl SharedKeys {
+    /// Fails if there is no key or a key is shorter than `MIN_KEY_SIZE`
+    pub fn new(keys: Vec<Vec<u8>>) -> io::Result<Self> {
+        if keys.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "No key found"));
+        }
+
+        if keys.iter().any(|key| key.len() < MIN_KEY_SIZE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Keys must be at least {} bytes long", MIN_KEY_SIZE)));
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Read keys from a file, one hex encoded key per line
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::new(parse_lines(path, "shared key", decode_hex)?)
+    }
+
+    /// Get a copy of `beacon` with a MAC service computed with the first key
+    /// MAC covers the beacon encoded without this service
+    pub fn sign(&self, beacon: &Beacon) -> Result<Beacon, BeaconError> {
+        // There is at least one key, checked by `new`
+        let key = &self.keys[0];
+        let tag = mac(key, &beacon.as_bytes()?).finalize().into_bytes();
+
+        let mut signed = beacon.clone();
+        signed.services.push(Service::Unknown(MAC_SERVICE_TAG, Value::Bytes(tag.to_vec())));
+        Ok(signed)
+    }
+
+    /// Check MAC service of `beacon` against every key
+    /// Returns beacon without its MAC service
+    pub fn verify(&self, mut beacon: Beacon) -> Result<Beacon, BeaconError> {
+        let mut macs = beacon.services.iter()
+            .enumerate()
+            .filter(|(_, service)| matches!(service, Service::Unknown(MAC_SERVICE_TAG, _)));
+
+        let position = match (macs.next(), macs.next()) {
+            (Some((position, _)), None) => position,
+            (None, _) => return Err(BeaconError::Unauthenticated),
+            (Some(_), Some(_)) => return Err(BeaconError::BadAuthentication),
+        };
+
+        let Service::Unknown(_, Value::Bytes(tag)) = beacon.services.remove(position) else {
+            return Err(BeaconError::BadAuthentication);
+        };
+
+        let bytes = beacon.as_bytes()?;
+
+        if self.keys.iter().any(|key| mac(key, &bytes).verify_slice(&tag).is_ok()) {
+            Ok(beacon)
+        } else {
+            Err(BeaconError::BadAuthentication)
+        }
+    }
+}
+
+fn mac(key: &[u8], bytes: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key)
+        .expect("HMAC accepts keys of any size");
+    mac.update(bytes);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_cbor::Value;
+
+    use crate::beacon::{Beacon, BeaconError, Service};
+    use crate::testing::beacon;
+
+    use super::{SharedKeys, MAC_SERVICE_TAG, MIN_KEY_SIZE};
+
+    fn with_cla() -> Beacon {
+        let mut beacon = beacon(1);
+        beacon.services.push(Service::TCPCLv4(4556));
+        beacon
+    }
+
+    fn keys(keys: &[u8]) -> SharedKeys {
+        SharedKeys::new(keys.iter().map(|it| vec![*it; MIN_KEY_SIZE]).collect()).unwrap()
+    }
+
+    #[test]
+    fn rejects_missing_or_short_keys() {
+        assert!(SharedKeys::new(Vec::new()).is_err());
+        assert!(SharedKeys::new(vec![vec![1; MIN_KEY_SIZE - 1]]).is_err());
+    }
+
+    #[test]
+    fn verifies_signed_beacon() {
+        let keys = keys(&[1]);
+        let signed = keys.sign(&with_cla()).unwrap();
+        let received = Beacon::parse(&signed.as_bytes().unwrap()).unwrap();
+
+        let verified = keys.verify(received).unwrap();
+
+        assert_eq!(verified.services, with_cla().services);
+    }
+
+    #[test]
+    fn accepts_any_key_for_rollover() {
+        let signed = keys(&[2]).sign(&with_cla()).unwrap();
+        assert!(keys(&[1, 2]).verify(signed).is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_key_or_tampered_beacon() {
+        let signed = keys(&[1]).sign(&with_cla()).unwrap();
+        assert!(matches!(keys(&[2]).verify(signed.clone()), Err(BeaconError::BadAuthentication)));
+
+        let mut tampered = signed;
+        tampered.sequence_number += 1;
+        assert!(matches!(keys(&[1]).verify(tampered), Err(BeaconError::BadAuthentication)));
+    }
+
+    #[test]
+    fn rejects_missing_or_duplicate_mac() {
+        assert!(matches!(keys(&[1]).verify(with_cla()), Err(BeaconError::Unauthenticated)));
+
+        let mut twice = keys(&[1]).sign(&with_cla()).unwrap();
+        twice.services.push(Service::Unknown(MAC_SERVICE_TAG, Value::Bytes(vec![0; 32])));
+        assert!(matches!(keys(&[1]).verify(twice), Err(BeaconError::BadAuthentication)));
+    }
+}
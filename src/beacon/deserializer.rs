@@ -1,133 +1,304 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::{Deserialize, de::{Visitor, Error}};
-use super::flags::{SOURCE_EID_PRESENT, SERVICE_BLOCK_PRESENT, BEACON_PERIOD_PRESENT};
-use super::Service;
-use super::Beacon;
+use serde::{Deserialize, de::Error};
+use serde_cbor::Value;
+use super::flags::{SOURCE_EID_PRESENT, SERVICE_BLOCK_PRESENT, BEACON_PERIOD_PRESENT, TIMESTAMP_PRESENT, ENCRYPTED, FIELD_FLAGS, KNOWN_FLAGS, SOLICITATION};
+use super::{Beacon, BeaconError, Service};
 
 impl<'de> Deserialize<'de> for super::Beacon {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D)
         -> Result<Self, D::Error> {
-        deserializer.deserialize_seq(BeaconVisitor)
+        let value = Value::deserialize(deserializer)?;
+        decode_beacon(value, false).map_err(Error::custom)
     }
 }
 
-struct BeaconVisitor;
+/// Decode a beacon from its CBOR structure
+/// In strict mode, unknown flags and extra fields are rejected
+pub(super) fn decode_beacon(value: Value, strict: bool) -> Result<Beacon, BeaconError> {
+    let Value::Array(fields) = value else {
+        return Err(BeaconError::InvalidField("beacon array"));
+    };
 
-impl<'de> Visitor<'de> for BeaconVisitor {
-    type Value = Beacon;
+    let found = fields.len();
+    let mut fields = fields.into_iter();
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(formatter, "a beacon definition")
+    let version: u8 = match fields.next() {
+        Some(value) => decode_uint(value, "beacon version")?,
+        None => return Err(BeaconError::Truncated),
+    };
+
+    if version != 8 {
+        return Err(BeaconError::UnsupportedVersion(version));
     }
 
-    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A)
-        -> Result<Self::Value, A::Error> {
+    let flags: u8 = match fields.next() {
+        Some(value) => decode_uint(value, "beacon flags")?,
+        None => return Err(BeaconError::Truncated),
+    };
 
-            let version:u8 = seq.next_element()?
-                .ok_or(Error::missing_field("beacon version"))?;
+    if flags & ENCRYPTED == ENCRYPTED {
+        return Err(BeaconError::Encrypted);
+    }
 
-            if version != 8 {
-                return Err(Error::custom(format!("Unsupported beacon format {}", version)));
-            }
+    if strict && flags & !KNOWN_FLAGS != 0 {
+        return Err(BeaconError::UnknownFlags(flags));
+    }
 
-            let flags: u8 = seq.next_element()?
-                .ok_or(Error::missing_field("beacon flags"))?;
-
-            let sequence_number: u64 = seq.next_element()?
-                .ok_or(Error::missing_field("beacon sequence number"))?;
-
-            let node_id:Option<String> = 
-                if flags & SOURCE_EID_PRESENT == SOURCE_EID_PRESENT {
-                    Some(seq.next_element()?
-                            .ok_or(Error::missing_field("beacon source node ID"))?)
-                } else {
-                    None
-                };
-            
-            let services:Vec<Service> = 
-                if flags & SERVICE_BLOCK_PRESENT == SERVICE_BLOCK_PRESENT {
-                    seq.next_element()?
-                        .ok_or(Error::missing_field("beacon service block"))?
-                } else {
-                    Vec::new()
-                };
-            
-            let period:Option<Duration> =
-                if flags & BEACON_PERIOD_PRESENT == BEACON_PERIOD_PRESENT {
-                    let duration = Duration::from_secs(
-                        seq.next_element()?
-                            .ok_or(Error::missing_field("beacon period"))?);
-
-                    Some(duration)
-                } else {
-                    None
-                };
-
-            Ok(Beacon { version, node_id, sequence_number, services, period })
+    let expected = 3 + (flags & FIELD_FLAGS).count_ones() as usize;
 
+    if found < expected || (strict && found > expected) {
+        return Err(BeaconError::FieldMismatch { flags, expected, found });
     }
 
+    // Fields count has been checked above, every announced field is present
+    let sequence_number: u64 = decode_uint(fields.next().unwrap(), "beacon sequence number")?;
+
+    let node_id: Option<String> =
+        if flags & SOURCE_EID_PRESENT == SOURCE_EID_PRESENT {
+            match fields.next().unwrap() {
+                Value::Text(node_id) => Some(node_id),
+                _ => return Err(BeaconError::InvalidField("beacon source node ID"))
+            }
+        } else {
+            None
+        };
+
+    let services: Vec<Service> =
+        if flags & SERVICE_BLOCK_PRESENT == SERVICE_BLOCK_PRESENT {
+            match fields.next().unwrap() {
+                Value::Array(services) => services.into_iter()
+                    .map(decode_service)
+                    .collect::<Result<_, _>>()?,
+                _ => return Err(BeaconError::InvalidField("beacon service block"))
+            }
+        } else {
+            Vec::new()
+        };
+
+    let period: Option<Duration> =
+        if flags & BEACON_PERIOD_PRESENT == BEACON_PERIOD_PRESENT {
+            Some(Duration::from_secs(
+                decode_uint(fields.next().unwrap(), "beacon period")?))
+        } else {
+            None
+        };
+
+    let timestamp: Option<SystemTime> =
+        if flags & TIMESTAMP_PRESENT == TIMESTAMP_PRESENT {
+            let secs = decode_uint(fields.next().unwrap(), "beacon timestamp")?;
+            Some(UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+                .ok_or(BeaconError::InvalidField("beacon timestamp"))?)
+        } else {
+            None
+        };
+
+    let solicitation = flags & SOLICITATION == SOLICITATION;
+
+    Ok(Beacon { version, node_id, sequence_number, services, period, timestamp, solicitation })
 }
 
 impl<'de> Deserialize<'de> for super::Service {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D)
         -> Result<Self, D::Error> {
-            deserializer.deserialize_tuple(2, ServiceVisitor)
+            let value = Value::deserialize(deserializer)?;
+            decode_service(value).map_err(Error::custom)
     }
 }
 
-struct ServiceVisitor;
-
-impl<'de> Visitor<'de> for ServiceVisitor {
-    type Value = super::Service;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter)
-        -> std::fmt::Result {
-            write!(formatter, "a service definition tuple (tag, data)")
-    }
-
-    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A)
-        -> Result<Self::Value, A::Error> {
-            let tag:u8 = seq.next_element()?
-                .ok_or(Error::missing_field("service tag"))?;
-
-            match tag {
-                0 => {
-                    let port = seq.next_element()?
-                        .ok_or(Error::missing_field("convergence layer port"))?;
-                    Ok(super::Service::TCPCLv4(port))
-                },
-
-                1 => {
-                    let port = seq.next_element()?
-                        .ok_or(Error::missing_field("convergence layer port"))?;
-                    Ok(super::Service::TCPCLv3(port))
-                },
-
-                2 => {
-                    let port = seq.next_element()?
-                        .ok_or(Error::missing_field("convergence layer port"))?;
-                    Ok(super::Service::MTCPCL(port))
-                },
-
-                64 => {
-                    let latlon: (f32, f32) = seq.next_element()?
-                        .ok_or(Error::missing_field("geo location data"))?;
-                    Ok(super::Service::GeoLocation(latlon.0, latlon.1))
-                },
-
-                65 => {
-                    let addr: String = seq.next_element()?
-                        .ok_or(Error::missing_field("address string"))?;
-                    Ok(super::Service::Address(addr))
-                },
-
-                unknown_tag => {
-                    let data = seq.next_element()?
-                        .ok_or(Error::missing_field("service data"))?;
-                    Ok(super::Service::Unknown(unknown_tag, data))
-                }
-            }
+/// Decode a service definition tuple (tag, data)
+fn decode_service(value: Value) -> Result<Service, BeaconError> {
+    let Value::Array(definition) = value else {
+        return Err(BeaconError::InvalidField("service definition"));
+    };
+
+    let mut definition = definition.into_iter();
+
+    let tag: u8 = match definition.next() {
+        Some(value) => decode_uint(value, "service tag")?,
+        None => return Err(BeaconError::InvalidField("service definition")),
+    };
+
+    let Some(data) = definition.next() else {
+        return Err(BeaconError::BadService(tag, "missing service data".into()));
+    };
+
+    if definition.next().is_some() {
+        return Err(BeaconError::BadService(tag, "too many elements".into()));
+    }
+
+    let port = |data: Value| decode_uint::<u16>(data, "convergence layer port")
+        .map_err(|_| BeaconError::BadService(tag, "invalid convergence layer port".into()));
+
+    match tag {
+        0 => Ok(Service::TCPCLv4(port(data)?)),
+
+        1 => Ok(Service::TCPCLv3(port(data)?)),
+
+        2 => Ok(Service::MTCPCL(port(data)?)),
+
+        64 => match data {
+            Value::Array(latlon) => match latlon.as_slice() {
+                [Value::Float(lat), Value::Float(lon)] =>
+                    Ok(Service::GeoLocation(*lat as f32, *lon as f32)),
+                _ => Err(BeaconError::BadService(tag, "invalid geo location data".into()))
+            },
+            _ => Err(BeaconError::BadService(tag, "invalid geo location data".into()))
+        },
+
+        65 => match data {
+            Value::Text(addr) => Ok(Service::Address(addr)),
+            _ => Err(BeaconError::BadService(tag, "invalid address string".into()))
+        },
+
+        unknown_tag => Ok(Service::Unknown(unknown_tag, data))
+    }
+}
+
+fn decode_uint<T: TryFrom<i128>>(value: Value, field: &'static str) -> Result<T, BeaconError> {
+    match value {
+        Value::Integer(i) => T::try_from(i).map_err(|_| BeaconError::InvalidField(field)),
+        _ => Err(BeaconError::InvalidField(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_cbor::Value;
+
+    use crate::beacon::{Beacon, BeaconError, Service};
+    use crate::testing::{beacon, NODE_ID};
+
+    fn encode(fields: Vec<Value>) -> Vec<u8> {
+        serde_cbor::to_vec(&Value::Array(fields)).unwrap()
+    }
+
+    fn int(value: i128) -> Value {
+        Value::Integer(value)
+    }
+
+    fn node_id() -> Value {
+        Value::Text(NODE_ID.into())
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut sent = beacon(42);
+        sent.services = vec![Service::TCPCLv4(4556), Service::Address("here".into())];
+        sent.period = Some(Duration::from_secs(30));
+
+        let parsed = Beacon::parse_strict(&sent.as_bytes().unwrap()).unwrap();
+
+        assert_eq!(parsed.node_id, sent.node_id);
+        assert_eq!(parsed.sequence_number, 42);
+        assert!(matches!(parsed.services.as_slice(),
+            [Service::TCPCLv4(4556), Service::Address(address)] if address == "here"));
+        assert_eq!(parsed.period, sent.period);
+    }
+
+    #[test]
+    fn solicitation_round_trip() {
+        let bytes = beacon(1).solicit().as_bytes().unwrap();
+
+        assert!(Beacon::parse_strict(&bytes).unwrap().solicitation);
+        assert!(!Beacon::parse_strict(&beacon(1).as_bytes().unwrap()).unwrap().solicitation);
+
+        // Flag without field
+        let fields: Value = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(fields, Value::Array(vec![int(8), int(0x09), int(1), node_id()]));
+    }
+
+    #[test]
+    fn unknown_flags_rejected_in_strict_mode_only() {
+        let bytes = encode(vec![int(8), int(0x41), int(1), node_id()]);
+
+        assert!(Beacon::parse(&bytes).is_ok());
+        assert!(matches!(Beacon::parse_strict(&bytes), Err(BeaconError::UnknownFlags(0x41))));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn extra_fields_rejected_in_strict_mode_only() {
+        let bytes = encode(vec![int(8), int(0x01), int(1), node_id(), int(7)]);
+
+        assert!(Beacon::parse(&bytes).is_ok());
+        assert!(matches!(Beacon::parse_strict(&bytes),
+            Err(BeaconError::FieldMismatch { expected: 4, found: 5, .. })));
+    }
+
+    #[test]
+    fn missing_fields_rejected() {
+        // Period announced but absent
+        let bytes = encode(vec![int(8), int(0x05), int(1), node_id()]);
+
+        assert!(matches!(Beacon::parse(&bytes),
+            Err(BeaconError::FieldMismatch { expected: 5, found: 4, .. })));
+        assert!(matches!(Beacon::parse(&encode(vec![int(8)])), Err(BeaconError::Truncated)));
+    }
+
+    #[test]
+    fn truncated_and_trailing_data_rejected() {
+        let bytes = encode(vec![int(8), int(0x01), int(1), node_id()]);
+
+        assert!(matches!(Beacon::parse(&bytes[..bytes.len() - 2]), Err(BeaconError::Truncated)));
+        assert!(matches!(Beacon::parse(&[bytes.as_slice(), &[0]].concat()), Err(BeaconError::TrailingData)));
+    }
+
+    #[test]
+    fn unsupported_version_rejected() {
+        let bytes = encode(vec![int(7), int(0), int(1)]);
+        assert!(matches!(Beacon::parse(&bytes), Err(BeaconError::UnsupportedVersion(7))));
+    }
+
+    #[test]
+    fn hostile_integers_rejected() {
+        // Negative integers, down to the smallest one CBOR can carry
+        for value in [-1, -i128::from(u64::MAX) - 1] {
+            let version = encode(vec![int(value), int(0), int(1)]);
+            assert!(matches!(Beacon::parse(&version), Err(BeaconError::InvalidField("beacon version"))));
+
+            let flags = encode(vec![int(8), int(value), int(1)]);
+            assert!(matches!(Beacon::parse(&flags), Err(BeaconError::InvalidField("beacon flags"))));
+
+            let sequence_number = encode(vec![int(8), int(0), int(value)]);
+            assert!(matches!(Beacon::parse(&sequence_number), Err(BeaconError::InvalidField("beacon sequence number"))));
+
+            let period = encode(vec![int(8), int(0x04), int(1), int(value)]);
+            assert!(matches!(Beacon::parse(&period), Err(BeaconError::InvalidField("beacon period"))));
+
+            let port = encode(vec![int(8), int(0x02), int(1), Value::Array(vec![Value::Array(vec![int(0), int(value)])])]);
+            assert!(matches!(Beacon::parse(&port), Err(BeaconError::BadService(0, _))));
+
+            let tag = encode(vec![int(8), int(0x02), int(1), Value::Array(vec![Value::Array(vec![int(value), int(1)])])]);
+            assert!(matches!(Beacon::parse(&tag), Err(BeaconError::InvalidField("service tag"))));
+        }
+
+        // Largest integer CBOR can carry, too big for u8 and u16 fields
+        let value = i128::from(u64::MAX);
+
+        let flags = encode(vec![int(8), int(value), int(1)]);
+        assert!(matches!(Beacon::parse(&flags), Err(BeaconError::InvalidField("beacon flags"))));
+
+        let port = encode(vec![int(8), int(0x02), int(1), Value::Array(vec![Value::Array(vec![int(0), int(value)])])]);
+        assert!(matches!(Beacon::parse(&port), Err(BeaconError::BadService(0, _))));
+
+        let tag = encode(vec![int(8), int(0x02), int(1), Value::Array(vec![Value::Array(vec![int(value), int(1)])])]);
+        assert!(matches!(Beacon::parse(&tag), Err(BeaconError::InvalidField("service tag"))));
+    }
+
+    #[test]
+    fn largest_period_accepted() {
+        let bytes = encode(vec![int(8), int(0x04), int(1), int(u64::MAX.into())]);
+        let parsed = Beacon::parse(&bytes).unwrap();
+
+        assert_eq!(parsed.period, Some(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn rejects_timestamp_overflowing_system_time() {
+        let bytes = encode(vec![int(8), int(0x11), int(1), node_id(), int(u64::MAX.into())]);
+
+        assert!(matches!(Beacon::parse(&bytes), Err(BeaconError::InvalidField("beacon timestamp"))));
+    }
+}
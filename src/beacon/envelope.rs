@@ -0,0 +1,175 @@
+use std::{fmt::Debug, io, path::Path};
+
+use chacha20poly1305::{aead::{Aead, Payload}, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand::Rng;
+use serde_cbor::Value;
+
+use crate::util::{decode_hex, parse_lines};
+use super::{flags::ENCRYPTED, BeaconError};
+
+/// Size of envelope nonce, in bytes
+const NONCE_SIZE: usize = 12;
+
+/// Pre-shared group keys encrypting beacons (ChaCha20-Poly1305)
+/// Beacons are sealed with the first key and opened with the key named in the envelope,
+/// so keys can be rotated
+#[derive(Clone)]
+pub struct GroupKeys {
+    keys: Vec<(u8, Key)>
+}
+
+impl Debug for GroupKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ids: Vec<u8> = self.keys.iter().map(|(id, _)| *id).collect();
+        write!(f, "GroupKeys({:?})", ids)
+    }
+}
+
+impl GroupKeys {
+    /// Fails if there is no key
+    pub fn new(keys: Vec<(u8, [u8; 32])>) -> io::Result<Self> {
+        if keys.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "No key found"));
+        }
+
+        Ok(Self { keys: keys.into_iter().map(|(id, key)| (id, Key::from(key))).collect() })
+    }
+
+    /// Read keys from a file, one `KEY_ID HEX_KEY` per line with a 32 bytes key
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::new(parse_lines(path, "group key", |line| line.split_once(char::is_whitespace)
+            .and_then(|(id, key)| Some((
+                id.parse::<u8>().ok()?,
+                decode_hex(key.trim())?.try_into().ok()?
+            ))))?)
+    }
+
+    /// Seal an encoded beacon in an envelope with the first key
+    /// Envelope is `[version, flags, key ID, nonce + ciphertext]`, version and key ID are authenticated
+    pub fn seal(&self, beacon: &[u8]) -> Result<Vec<u8>, BeaconError> {
+        // There is at least one key, checked by `new`
+        let (key_id, key) = &self.keys[0];
+
+        let mut nonce = [0_u8; NONCE_SIZE];
+        rand::thread_rng().fill(&mut nonce);
+
+        let ciphertext = ChaCha20Poly1305::new(key)
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: beacon, aad: &[8, *key_id] })
+            .map_err(|_| BeaconError::BadEnvelope)?;
+
+        let sealed = [nonce.as_slice(), &ciphertext].concat();
+
+        Ok(serde_cbor::to_vec(&Value::Array(vec![
+            Value::Integer(8),
+            Value::Integer(ENCRYPTED.into()),
+            Value::Integer((*key_id).into()),
+            Value::Bytes(sealed)
+        ]))?)
+    }
+
+    /// Open an envelope, returns the encoded beacon it contains
+    pub fn open(&self, datagram: &[u8]) -> Result<Vec<u8>, BeaconError> {
+        let Value::Array(fields) = serde_cbor::from_slice(datagram)? else {
+            return Err(BeaconError::NotEncrypted);
+        };
+
+        let (key_id, sealed) = match fields.as_slice() {
+            [Value::Integer(8), Value::Integer(flags), Value::Integer(key_id), Value::Bytes(sealed)]
+                if *flags == ENCRYPTED.into() => (*key_id, sealed),
+            [Value::Integer(8), ..] => return Err(BeaconError::NotEncrypted),
+            [Value::Integer(version), ..] => return Err(BeaconError::UnsupportedVersion(
+                u8::try_from(*version).unwrap_or(u8::MAX))),
+            _ => return Err(BeaconError::InvalidField("beacon envelope")),
+        };
+
+        let Some((key_id, key)) = self.keys.iter().find(|(id, _)| i128::from(*id) == key_id) else {
+            return Err(BeaconError::UnknownGroupKey);
+        };
+
+        if sealed.len() < NONCE_SIZE {
+            return Err(BeaconError::BadEnvelope);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+        ChaCha20Poly1305::new(key)
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &[8, *key_id] })
+            .map_err(|_| BeaconError::BadEnvelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_cbor::Value;
+
+    use crate::beacon::{Beacon, BeaconError};
+    use crate::testing::beacon;
+
+    use super::GroupKeys;
+
+    fn encoded() -> Vec<u8> {
+        beacon(1).as_bytes().unwrap()
+    }
+
+    #[test]
+    fn rejects_empty_key_list() {
+        assert!(GroupKeys::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys = GroupKeys::new(vec![(1, [1; 32])]).unwrap();
+        let sealed = keys.seal(&encoded()).unwrap();
+
+        assert_eq!(keys.open(&sealed).unwrap(), encoded());
+        assert!(matches!(Beacon::parse(&sealed), Err(BeaconError::Encrypted)));
+    }
+
+    #[test]
+    fn opens_with_any_key_for_rotation() {
+        let sealed = GroupKeys::new(vec![(2, [2; 32])]).unwrap().seal(&encoded()).unwrap();
+        let keys = GroupKeys::new(vec![(1, [1; 32]), (2, [2; 32])]).unwrap();
+
+        assert_eq!(keys.open(&sealed).unwrap(), encoded());
+    }
+
+    #[test]
+    fn rejects_wrong_key_or_key_id() {
+        let sealed = GroupKeys::new(vec![(1, [1; 32])]).unwrap().seal(&encoded()).unwrap();
+
+        let wrong_key = GroupKeys::new(vec![(1, [2; 32])]).unwrap();
+        assert!(matches!(wrong_key.open(&sealed), Err(BeaconError::BadEnvelope)));
+
+        let unknown_id = GroupKeys::new(vec![(2, [1; 32])]).unwrap();
+        assert!(matches!(unknown_id.open(&sealed), Err(BeaconError::UnknownGroupKey)));
+    }
+
+    #[test]
+    fn rejects_tampered_envelope() {
+        let keys = GroupKeys::new(vec![(1, [1; 32]), (2, [1; 32])]).unwrap();
+        let sealed = keys.seal(&encoded()).unwrap();
+
+        let Value::Array(mut fields) = serde_cbor::from_slice(&sealed).unwrap() else {
+            panic!("Envelope is not an array");
+        };
+
+        // Key ID is authenticated, moving to another key with same bytes fails
+        fields[2] = Value::Integer(2);
+        let moved = serde_cbor::to_vec(&Value::Array(fields.clone())).unwrap();
+        assert!(matches!(keys.open(&moved), Err(BeaconError::BadEnvelope)));
+
+        fields[2] = Value::Integer(1);
+        if let Value::Bytes(sealed) = &mut fields[3] {
+            let last = sealed.len() - 1;
+            sealed[last] ^= 1;
+        }
+        let tampered = serde_cbor::to_vec(&Value::Array(fields)).unwrap();
+        assert!(matches!(keys.open(&tampered), Err(BeaconError::BadEnvelope)));
+    }
+
+    #[test]
+    fn rejects_plain_beacon() {
+        let keys = GroupKeys::new(vec![(1, [1; 32])]).unwrap();
+        assert!(matches!(keys.open(&encoded()), Err(BeaconError::NotEncrypted)));
+    }
+}
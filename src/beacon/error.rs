@@ -0,0 +1,128 @@
+use std::fmt::Display;
+
+/// Reason a beacon could not be encoded or decoded
+#[derive(Debug)]
+pub enum BeaconError {
+    /// Version number is not 8
+    UnsupportedVersion(u8),
+
+    /// Flag has bits set that this implementation does not know (strict mode only)
+    UnknownFlags(u8),
+
+    /// Number of fields in beacon does not match the flag bits
+    FieldMismatch {
+        flags: u8,
+        expected: usize,
+        found: usize
+    },
+
+    /// Datagram ends before the end of the beacon
+    Truncated,
+
+    /// Datagram contains bytes after the beacon
+    TrailingData,
+
+    /// A field does not have the expected type
+    InvalidField(&'static str),
+
+    /// A service of the service block can't be decoded
+    /// (Service tag, reason)
+    BadService(u8, String),
+
+    /// Beacon is bigger than `MAX_BEACON_SIZE`
+    Oversize(usize),
+
+    /// Beacon has no MAC while shared keys are required
+    Unauthenticated,
+
+    /// Beacon MAC or signature does not match any key
+    BadAuthentication,
+
+    /// No key is trusted to sign beacons of this node
+    UntrustedNode,
+
+    /// Beacon has no timestamp or its timestamp is outside freshness window
+    NotFresh,
+
+    /// Beacon is older than the last one accepted from its node
+    Replayed,
+
+    /// Beacon is encrypted and no group key is configured
+    Encrypted,
+
+    /// Beacon is not encrypted while group keys are required
+    NotEncrypted,
+
+    /// Envelope key ID does not match any group key
+    UnknownGroupKey,
+
+    /// Envelope can't be sealed or opened
+    BadEnvelope,
+
+    /// Data is not valid CBOR
+    Cbor(serde_cbor::Error)
+}
+
+impl BeaconError {
+    /// Short name of the cause, used to count invalid beacons
+    pub fn cause(&self) -> &'static str {
+        match self {
+            BeaconError::UnsupportedVersion(_) => "unsupported version",
+            BeaconError::UnknownFlags(_) => "unknown flags",
+            BeaconError::FieldMismatch { .. } => "flag/field mismatch",
+            BeaconError::Truncated => "truncated",
+            BeaconError::TrailingData => "trailing data",
+            BeaconError::InvalidField(_) => "invalid field",
+            BeaconError::BadService(_, _) => "bad service",
+            BeaconError::Oversize(_) => "oversize",
+            BeaconError::Unauthenticated => "unauthenticated",
+            BeaconError::BadAuthentication => "bad authentication",
+            BeaconError::UntrustedNode => "untrusted node",
+            BeaconError::NotFresh => "not fresh",
+            BeaconError::Replayed => "replayed",
+            BeaconError::Encrypted => "encrypted",
+            BeaconError::NotEncrypted => "not encrypted",
+            BeaconError::UnknownGroupKey => "unknown group key",
+            BeaconError::BadEnvelope => "bad envelope",
+            BeaconError::Cbor(_) => "invalid cbor",
+        }
+    }
+}
+
+impl Display for BeaconError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BeaconError::UnsupportedVersion(version) => write!(f, "Unsupported beacon format {}", version),
+            BeaconError::UnknownFlags(flags) => write!(f, "Unknown beacon flags {:#010b}", flags),
+            BeaconError::FieldMismatch { flags, expected, found } =>
+                write!(f, "Beacon flags {:#010b} announce {} fields but {} found", flags, expected, found),
+            BeaconError::Truncated => write!(f, "Beacon is truncated"),
+            BeaconError::TrailingData => write!(f, "Trailing data after beacon"),
+            BeaconError::InvalidField(field) => write!(f, "Invalid {}", field),
+            BeaconError::BadService(tag, reason) => write!(f, "Invalid service {} : {}", tag, reason),
+            BeaconError::Oversize(size) => write!(f, "Beacon too big ({} bytes)", size),
+            BeaconError::Unauthenticated => write!(f, "Beacon is not authenticated"),
+            BeaconError::BadAuthentication => write!(f, "Beacon authentication failed"),
+            BeaconError::UntrustedNode => write!(f, "No trusted key for beacon node"),
+            BeaconError::NotFresh => write!(f, "Beacon timestamp missing or outside freshness window"),
+            BeaconError::Replayed => write!(f, "Beacon is older than the last one accepted from its node"),
+            BeaconError::Encrypted => write!(f, "Beacon is encrypted"),
+            BeaconError::NotEncrypted => write!(f, "Beacon is not encrypted"),
+            BeaconError::UnknownGroupKey => write!(f, "Beacon is encrypted with an unknown group key"),
+            BeaconError::BadEnvelope => write!(f, "Beacon envelope can't be opened"),
+            BeaconError::Cbor(e) => write!(f, "Invalid CBOR : {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BeaconError {}
+
+impl From<serde_cbor::Error> for BeaconError {
+    fn from(value: serde_cbor::Error) -> Self {
+        if value.is_eof() {
+            BeaconError::Truncated
+        } else {
+            BeaconError::Cbor(value)
+        }
+    }
+}
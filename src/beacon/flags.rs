@@ -5,4 +5,21 @@ pub const SOURCE_EID_PRESENT: u8 = 0b0000_0001;
 pub const SERVICE_BLOCK_PRESENT: u8 = 0b0000_0010;
 
 /// Beacon Period field is present
-pub const BEACON_PERIOD_PRESENT: u8 = 0b0000_0100;
\ No newline at end of file
+pub const BEACON_PERIOD_PRESENT: u8 = 0b0000_0100;
+
+/// Timestamp field is present
+pub const TIMESTAMP_PRESENT: u8 = 0b0001_0000;
+
+/// Sender asks receivers to answer with a unicast beacon
+/// No field is associated with this flag
+pub const SOLICITATION: u8 = 0b0000_1000;
+
+/// Beacon is an encrypted envelope, see `envelope::GroupKeys`
+/// Envelope is `[version, flags, key ID, sealed beacon]`
+pub const ENCRYPTED: u8 = 0b1000_0000;
+
+/// Flags announcing a field
+pub const FIELD_FLAGS: u8 = SOURCE_EID_PRESENT | SERVICE_BLOCK_PRESENT | BEACON_PERIOD_PRESENT | TIMESTAMP_PRESENT;
+
+/// All flags known by this implementation
+pub const KNOWN_FLAGS: u8 = FIELD_FLAGS | SOLICITATION | ENCRYPTED;
\ No newline at end of file
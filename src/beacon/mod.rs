@@ -1,12 +1,23 @@
 mod serializer;
 mod deserializer;
-mod flags;
+pub mod flags;
+pub mod auth;
+pub mod envelope;
+pub mod signature;
+mod error;
 
-use std::{fmt::Display, net::IpAddr, time::Duration};
+use std::{fmt::Display, net::SocketAddr, time::{Duration, SystemTime}};
+use nix::net::if_::if_indextoname;
+use serde::Deserialize;
 use serde_cbor::Value;
 
+pub use error::BeaconError;
+
 pub type NodeIdentifier = String;
 
+/// Maximum size of an encoded beacon (maximum UDP payload over ipv4)
+pub const MAX_BEACON_SIZE: usize = 65_507;
+
 /// A beacon sent periodically to advertize a DTN node
 #[derive(Debug, Clone)]
 pub struct Beacon {
@@ -26,7 +37,19 @@ pub struct Beacon {
     pub services: Vec<Service>,
 
     /// Duration between two beacon advertizments
-    pub period: Option<Duration>
+    pub period: Option<Duration>,
+
+    /// Emission time, in whole seconds, used to reject replayed beacons
+    pub timestamp: Option<SystemTime>,
+
+    /// Sender asks receivers to answer with a unicast beacon
+    pub solicitation: bool
+}
+
+impl Default for Beacon {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Beacon {
@@ -38,7 +61,9 @@ impl Beacon {
             node_id: None, 
             sequence_number: 0,
             services: Vec::new(),
-            period: None
+            period: None,
+            timestamp: None,
+            solicitation: false
         }
     }
 
@@ -50,18 +75,65 @@ impl Beacon {
         next
     }
 
+    /// Get a goodbye beacon
+    /// Clone current beacon with a period of zero, telling neighbours this node stops announcing itself
+    pub fn goodbye(&self) -> Self {
+        let mut goodbye = self.clone();
+        goodbye.period = Some(Duration::ZERO);
+        goodbye
+    }
+
+    /// Get a solicitation
+    /// Clone current beacon asking receivers to answer with a unicast beacon
+    pub fn solicit(&self) -> Self {
+        let mut solicitation = self.clone();
+        solicitation.solicitation = true;
+        solicitation
+    }
+
+    /// Is this beacon a goodbye (period of zero)
+    pub fn is_goodbye(&self) -> bool {
+        self.period == Some(Duration::ZERO)
+    }
+
     /// Get beacon as bytes
-    pub fn as_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
-        serde_cbor::to_vec(&self)
+    pub fn as_bytes(&self) -> Result<Vec<u8>, BeaconError> {
+        let bytes = serde_cbor::to_vec(&self)?;
+
+        if bytes.len() > MAX_BEACON_SIZE {
+            return Err(BeaconError::Oversize(bytes.len()));
+        }
+
+        Ok(bytes)
+    }
+
+    /// Parse beacon from bytes
+    /// Unknown flags and extra fields are ignored for forward compatibility
+    pub fn parse(bytes: &[u8]) -> Result<Self, BeaconError> {
+        Self::parse_with(bytes, false)
     }
 
     /// Parse beacon from bytes
-    pub fn parse(bytes: &[u8]) -> Result<Self, serde_cbor::Error> {
-        serde_cbor::from_slice(bytes)
+    /// Rejects unknown flags and fields not announced by flags
+    pub fn parse_strict(bytes: &[u8]) -> Result<Self, BeaconError> {
+        Self::parse_with(bytes, true)
+    }
+
+    fn parse_with(bytes: &[u8], strict: bool) -> Result<Self, BeaconError> {
+        if bytes.len() > MAX_BEACON_SIZE {
+            return Err(BeaconError::Oversize(bytes.len()));
+        }
+
+        let mut deserializer = serde_cbor::Deserializer::from_slice(bytes);
+        let value = Value::deserialize(&mut deserializer)?;
+        deserializer.end().map_err(|_| BeaconError::TrailingData)?;
+
+        deserializer::decode_beacon(value, strict)
     }
 }
 
-#[derive(Debug, Clone)]
+/// A service advertized in a beacon service block
+#[derive(Debug, Clone, PartialEq)]
 pub enum Service {
     /// A TCP Convergence Layer v4 (RFC9174)
     /// First parameter is TCP port to connect to
@@ -99,11 +171,14 @@ impl Display for NotClaError {
 }
 
 impl Service {
+    /// Is this service a convergence layer
     pub fn is_cla(&self) -> bool {
         matches!(self, Service::TCPCLv4(_)|Service::TCPCLv3(_)|Service::MTCPCL(_))
     }
 
-    pub fn as_cla_address(&self, source_address: IpAddr) -> Result<String, NotClaError> {
+    /// Build ud3tn CLA address of this service reachable at `source_address`
+    /// Link-local ipv6 addresses keep their zone (`[fe80::1%eth0]`)
+    pub fn as_cla_address(&self, source_address: SocketAddr) -> Result<String, NotClaError> {
         match self {
             Service::TCPCLv4(port) => Ok(format!("tcpclv4:{}:{}", format_ip(source_address), port)),
             Service::TCPCLv3(port) => Ok(format!("tcpclv3:{}:{}", format_ip(source_address), port)),
@@ -113,13 +188,84 @@ impl Service {
     }
 }
 
-fn format_ip(ip: IpAddr) -> String {
-    match ip {
-        IpAddr::V4(ipv4_addr) => ipv4_addr.to_string(),
-        IpAddr::V6(ipv6_addr) => if let Some(ipv4_addr) = ipv6_addr.to_ipv4() {
+fn format_ip(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(v4) => v4.ip().to_string(),
+        SocketAddr::V6(v6) => if let Some(ipv4_addr) = v6.ip().to_ipv4_mapped() {
             ipv4_addr.to_string()
+        } else if v6.ip().is_unicast_link_local() && v6.scope_id() != 0 {
+            format!("[{}%{}]", v6.ip(), zone_name(v6.scope_id()))
         } else {
-            format!("[{}]", ipv6_addr)
+            format!("[{}]", v6.ip())
         },
     }
-}
\ No newline at end of file
+}
+
+/// Name of interface with index `scope_id`, or the index itself if unknown
+fn zone_name(scope_id: u32) -> String {
+    if_indextoname(scope_id).ok()
+        .and_then(|it| it.into_string().ok())
+        .filter(|it| !it.is_empty())
+        .unwrap_or(scope_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{SocketAddr, SocketAddrV6}, time::Duration};
+
+    use nix::net::if_::if_nametoindex;
+
+    use crate::testing::beacon;
+
+    use super::{Beacon, Service};
+
+    #[test]
+    fn goodbye_is_current_beacon_with_zero_period() {
+        let mut current = beacon(5);
+        current.period = Some(Duration::from_secs(30));
+
+        let goodbye = current.goodbye();
+
+        assert_eq!(goodbye.sequence_number, 5);
+        assert!(goodbye.is_goodbye());
+        assert!(!current.is_goodbye());
+
+        // No period means default period, not a goodbye
+        assert!(!beacon(5).is_goodbye());
+    }
+
+    #[test]
+    fn goodbye_survives_encoding() {
+        let bytes = beacon(5).goodbye().as_bytes().unwrap();
+        assert!(Beacon::parse(&bytes).unwrap().is_goodbye());
+    }
+
+    #[test]
+    fn cla_addresses() {
+        let v4: SocketAddr = "192.0.2.1:3003".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:3003".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:3003".parse().unwrap();
+
+        assert_eq!(Service::TCPCLv4(4556).as_cla_address(v4).unwrap(), "tcpclv4:192.0.2.1:4556");
+        assert_eq!(Service::TCPCLv3(4556).as_cla_address(mapped).unwrap(), "tcpclv3:192.0.2.1:4556");
+        assert_eq!(Service::MTCPCL(4556).as_cla_address(v6).unwrap(), "mtcp:[2001:db8::1]:4556");
+        assert!(Service::Address("here".into()).as_cla_address(v4).is_err());
+    }
+
+    #[test]
+    fn link_local_cla_address_keeps_zone() {
+        let ip = "fe80::1".parse().unwrap();
+        let lo = if_nametoindex("lo").unwrap();
+
+        let on_lo = SocketAddr::V6(SocketAddrV6::new(ip, 3003, 0, lo));
+        assert_eq!(Service::TCPCLv4(4556).as_cla_address(on_lo).unwrap(), "tcpclv4:[fe80::1%lo]:4556");
+
+        // No zone without scope
+        let unscoped = SocketAddr::V6(SocketAddrV6::new(ip, 3003, 0, 0));
+        assert_eq!(Service::TCPCLv4(4556).as_cla_address(unscoped).unwrap(), "tcpclv4:[fe80::1]:4556");
+
+        // Index is kept when interface is unknown
+        let unknown = SocketAddr::V6(SocketAddrV6::new(ip, 3003, 0, u32::MAX));
+        assert_eq!(Service::TCPCLv4(4556).as_cla_address(unknown).unwrap(), format!("tcpclv4:[fe80::1%{}]:4556", u32::MAX));
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Serialize, ser::SerializeSeq, ser::SerializeTuple};
-use super::flags::{SOURCE_EID_PRESENT, SERVICE_BLOCK_PRESENT, BEACON_PERIOD_PRESENT};
+use std::time::UNIX_EPOCH;
+
+use super::flags::{SOURCE_EID_PRESENT, SERVICE_BLOCK_PRESENT, BEACON_PERIOD_PRESENT, TIMESTAMP_PRESENT, SOLICITATION};
 
 impl Serialize for super::Beacon {
     fn serialize<S: serde::Serializer>(&self, serializer: S)
@@ -24,6 +26,15 @@ impl Serialize for super::Beacon {
                 l += 1;
             }
 
+            if self.timestamp.is_some() {
+                f |= TIMESTAMP_PRESENT;
+                l += 1;
+            }
+
+            if self.solicitation {
+                f |= SOLICITATION;
+            }
+
             (f, l)
         };
 
@@ -47,6 +58,11 @@ impl Serialize for super::Beacon {
             beacon.serialize_element(&period.as_secs())?;
         }
 
+        if let Some(timestamp) = &self.timestamp {
+            let secs = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            beacon.serialize_element(&secs)?;
+        }
+
         beacon.end()
     }
 }
@@ -0,0 +1,101 @@
+use std::{fmt::Debug, io, path::Path};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde_cbor::Value;
+
+use crate::util::{decode_hex, encode_hex, parse_lines};
+use super::{Beacon, BeaconError, Service};
+
+/// Private use service tag carrying the Ed25519 signature of a beacon
+pub const SIGNATURE_SERVICE_TAG: u8 = 129;
+
+/// Signs beacons with the Ed25519 key of this node
+#[derive(Clone)]
+pub struct BeaconSigner {
+    key: SigningKey
+}
+
+impl Debug for BeaconSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BeaconSigner({})", self.key_id())
+    }
+}
+
+impl BeaconSigner {
+    pub fn new(key: SigningKey) -> Self {
+        Self { key }
+    }
+
+    /// Read an hex encoded 32 bytes secret key from a file
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let seeds = parse_lines(path, "signing key (32 hex encoded bytes)",
+            |line| <[u8; 32]>::try_from(decode_hex(line)?).ok())?;
+
+        let [seed] = seeds.as_slice() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Signing key file must hold a single key"));
+        };
+
+        Ok(Self::new(SigningKey::from_bytes(seed)))
+    }
+
+    /// Key ID, the hex encoded public key
+    pub fn key_id(&self) -> String {
+        encode_hex(self.key.verifying_key().as_bytes())
+    }
+
+    /// Get a copy of `beacon` with a signature service
+    /// Signature covers the beacon encoded without this service
+    pub fn sign(&self, beacon: &Beacon) -> Result<Beacon, BeaconError> {
+        let signature = self.key.sign(&beacon.as_bytes()?);
+
+        let mut signed = beacon.clone();
+        signed.services.push(Service::Unknown(SIGNATURE_SERVICE_TAG, Value::Array(vec![
+            Value::Bytes(signature.to_bytes().to_vec()),
+            Value::Bytes(self.key.verifying_key().to_bytes().to_vec())
+        ])));
+        Ok(signed)
+    }
+}
+
+/// Signature service of a received beacon
+#[derive(Debug, Clone)]
+pub struct BeaconSignature {
+    pub signature: Signature,
+
+    /// Public key of signer, if advertized
+    pub key_id: Option<VerifyingKey>
+}
+
+/// Remove signature service from `beacon`
+/// Returns beacon encoded without signature, as signed, and the signature
+pub fn take_signature(beacon: &mut Beacon) -> Result<(Vec<u8>, BeaconSignature), BeaconError> {
+    let mut signatures = beacon.services.iter()
+        .enumerate()
+        .filter(|(_, service)| matches!(service, Service::Unknown(SIGNATURE_SERVICE_TAG, _)));
+
+    let position = match (signatures.next(), signatures.next()) {
+        (Some((position, _)), None) => position,
+        (None, _) => return Err(BeaconError::Unauthenticated),
+        (Some(_), Some(_)) => return Err(BeaconError::BadAuthentication),
+    };
+
+    let Service::Unknown(_, Value::Array(fields)) = beacon.services.remove(position) else {
+        return Err(BeaconError::BadAuthentication);
+    };
+
+    let signature = match fields.first() {
+        Some(Value::Bytes(bytes)) => Signature::from_slice(bytes)
+            .map_err(|_| BeaconError::BadAuthentication)?,
+        _ => return Err(BeaconError::BadAuthentication),
+    };
+
+    let key_id = match fields.get(1) {
+        Some(Value::Bytes(bytes)) => Some(<[u8; 32]>::try_from(bytes.as_slice()).ok()
+            .and_then(|it| VerifyingKey::from_bytes(&it).ok())
+            .ok_or(BeaconError::BadAuthentication)?),
+        None => None,
+        _ => return Err(BeaconError::BadAuthentication),
+    };
+
+    Ok((beacon.as_bytes()?, BeaconSignature { signature, key_id }))
+}
@@ -3,12 +3,9 @@ use std::io::Write;
 use std::time::Duration;
 use std::str::FromStr;
 
+use archipel_ipbeacon::{Beacon, Service};
 use clap::Parser;
 
-use crate::beacon::Beacon;
-
-mod beacon;
-
 #[derive(Debug, Parser)]
 #[command(about="Create IPNDv8 beacon and output it to stdout", long_about = None)]
 struct CLIArgs {
@@ -51,15 +48,15 @@ fn main() {
     base_beacon.period = args.period_secs.map(Duration::from_secs);
 
     if let Some(port) = args.tcpclv3 {
-        base_beacon.services.push(beacon::Service::TCPCLv3(port));
+        base_beacon.services.push(Service::TCPCLv3(port));
     }
 
     if let Some(port) = args.tcpclv4 {
-        base_beacon.services.push(beacon::Service::TCPCLv4(port));
+        base_beacon.services.push(Service::TCPCLv4(port));
     }
 
     if let Some(port) = args.mtcpcl {
-        base_beacon.services.push(beacon::Service::MTCPCL(port));
+        base_beacon.services.push(Service::MTCPCL(port));
     }
 
     if let Some(str) = args.geolocation {
@@ -68,14 +65,14 @@ fn main() {
                                 .map(|it| it.expect("Failed to parse location part"))
                                 .collect();
 
-        base_beacon.services.push(beacon::Service::GeoLocation(
+        base_beacon.services.push(Service::GeoLocation(
             *parts.first().expect("Missing latitude"),
             *parts.get(1).expect("Missing longitude")
         ));
     }
 
     if let Some(address) = args.address {
-        base_beacon.services.push(beacon::Service::Address(address));
+        base_beacon.services.push(Service::Address(address));
     }
 
     std::io::stdout().write_all(&base_beacon.as_bytes().unwrap()).unwrap();
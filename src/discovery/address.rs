@@ -0,0 +1,172 @@
+use std::{cmp::Reverse, net::{IpAddr, SocketAddr}};
+
+use clap::ValueEnum;
+
+use super::neighbours::{Neighbour, LOST_AFTER_PERIODS};
+
+/// Which kind of address of a neighbour is preferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ScopePreference {
+    /// Prefer global (and unique local) addresses over link-local ones
+    #[default]
+    Global,
+
+    /// Prefer link-local addresses
+    LinkLocal,
+
+    /// No preference
+    Any
+}
+
+/// Which IP family of a neighbour is preferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum FamilyPreference {
+    Ipv4,
+    Ipv6,
+
+    /// No preference
+    #[default]
+    Any
+}
+
+/// How the address of a neighbour is chosen among the ones it was heard from
+/// Family preference comes first, then scope preference
+#[derive(Debug, Clone, Default)]
+pub struct AddressPolicy {
+    pub family: FamilyPreference,
+    pub scope: ScopePreference
+}
+
+impl AddressPolicy {
+
+    /// Select address contacts to a neighbour go through
+    /// Only addresses heard recently are considered, so another path is
+    /// selected when the preferred one goes away
+    pub fn select(&self, neighbour: &Neighbour) -> Option<SocketAddr> {
+        let oldest = neighbour.period.checked_mul(LOST_AFTER_PERIODS)
+            .and_then(|it| neighbour.last_seen.checked_sub(it));
+
+        // Equally ranked addresses are ordered by value, not by last beacon,
+        // so selection does not switch between paths on each beacon
+        neighbour.addresses.iter()
+            .filter(|(_, seen)| oldest.is_none_or(|oldest| **seen >= oldest))
+            .map(|(addr, _)| *addr)
+            .max_by_key(|addr| (self.family_rank(addr), self.scope_rank(addr), Reverse(*addr)))
+    }
+
+    /// Higher is better
+    fn family_rank(&self, addr: &SocketAddr) -> u8 {
+        match (self.family, addr.ip().to_canonical()) {
+            (FamilyPreference::Ipv4, IpAddr::V4(_)) => 1,
+            (FamilyPreference::Ipv6, IpAddr::V6(_)) => 1,
+            _ => 0
+        }
+    }
+
+    /// Higher is better
+    fn scope_rank(&self, addr: &SocketAddr) -> u8 {
+        match (self.scope, is_link_local(addr)) {
+            (ScopePreference::Global, false) => 1,
+            (ScopePreference::LinkLocal, true) => 1,
+            _ => 0
+        }
+    }
+}
+
+fn is_link_local(addr: &SocketAddr) -> bool {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_unicast_link_local(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{SocketAddr, SocketAddrV6}, time::Instant};
+
+    use crate::discovery::neighbours::{Neighbour, NeighbourTable, DEFAULT_PERIOD};
+    use crate::testing::{beacon, NODE_ID};
+
+    use super::{AddressPolicy, FamilyPreference, ScopePreference};
+
+    fn link_local() -> SocketAddr {
+        SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 3003, 0, 2))
+    }
+
+    fn global() -> SocketAddr {
+        "[2001:db8::1]:3003".parse().unwrap()
+    }
+
+    fn ipv4() -> SocketAddr {
+        "192.0.2.1:3003".parse().unwrap()
+    }
+
+    /// Neighbour that sent the same beacon from each address
+    fn heard_from(addresses: &[SocketAddr]) -> Neighbour {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        for address in addresses {
+            table.update(&node_id, &beacon(1), *address, now);
+        }
+
+        table.get(&node_id).unwrap().clone()
+    }
+
+    #[test]
+    fn prefers_global_addresses() {
+        let policy = AddressPolicy::default();
+
+        assert_eq!(policy.select(&heard_from(&[link_local(), global()])), Some(global()));
+        assert_eq!(policy.select(&heard_from(&[global(), link_local()])), Some(global()));
+        assert_eq!(policy.select(&heard_from(&[link_local()])), Some(link_local()));
+    }
+
+    #[test]
+    fn prefers_link_local_addresses() {
+        let policy = AddressPolicy { scope: ScopePreference::LinkLocal, ..AddressPolicy::default() };
+
+        assert_eq!(policy.select(&heard_from(&[global(), link_local()])), Some(link_local()));
+        assert_eq!(policy.select(&heard_from(&[global()])), Some(global()));
+    }
+
+    #[test]
+    fn family_preference_comes_first() {
+        let ipv6 = AddressPolicy { family: FamilyPreference::Ipv6, ..AddressPolicy::default() };
+        let ipv4_only = AddressPolicy { family: FamilyPreference::Ipv4, ..AddressPolicy::default() };
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:3003".parse().unwrap();
+
+        assert_eq!(ipv6.select(&heard_from(&[ipv4(), link_local()])), Some(link_local()));
+        assert_eq!(ipv6.select(&heard_from(&[ipv4(), link_local(), global()])), Some(global()));
+        assert_eq!(ipv4_only.select(&heard_from(&[global(), ipv4()])), Some(ipv4()));
+        assert_eq!(ipv4_only.select(&heard_from(&[global(), mapped])), Some(mapped));
+    }
+
+    #[test]
+    fn equal_addresses_do_not_alternate() {
+        let policy = AddressPolicy::default();
+
+        assert_eq!(policy.select(&heard_from(&[ipv4(), global()])), Some(ipv4()));
+        assert_eq!(policy.select(&heard_from(&[global(), ipv4()])), Some(ipv4()));
+    }
+
+    #[test]
+    fn switches_path_when_preferred_one_goes_away() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let policy = AddressPolicy { family: FamilyPreference::Ipv6, ..AddressPolicy::default() };
+        let mut table = NeighbourTable::new();
+
+        table.update(&node_id, &beacon(1), global(), now);
+        table.update(&node_id, &beacon(1), ipv4(), now);
+        assert_eq!(policy.select(table.get(&node_id).unwrap()), Some(global()));
+
+        // Only heard over ipv4 from now on
+        table.update(&node_id, &beacon(2), ipv4(), now + DEFAULT_PERIOD);
+        assert_eq!(policy.select(table.get(&node_id).unwrap()), Some(global()));
+
+        table.update(&node_id, &beacon(3), ipv4(), now + DEFAULT_PERIOD * 3);
+        assert_eq!(policy.select(table.get(&node_id).unwrap()), Some(ipv4()));
+    }
+}
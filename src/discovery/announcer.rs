@@ -1,61 +1,212 @@
-use std::{net::UdpSocket, net::SocketAddr, sync::{atomic::AtomicBool, Arc}, thread, time::Duration};
+use std::{net::{Ipv4Addr, SocketAddr, SocketAddrV6, UdpSocket}, sync::Arc, time::{Duration, Instant, SystemTime}};
 
-use crate::{beacon::Beacon, IpConfig};
-use std::sync::atomic::Ordering;
+use socket2::SockRef;
+
+use crate::beacon::Beacon;
+use super::{interfaces::Interface, schedule::{Schedule, ScheduleReset}, stop::StopSignal, uses_default_interface, DiscoveryConfig, IpConfig, SharedBeacon, SharedInterfaces, SharedSocket, BROADCAST_V4, BROADCAST_V6};
+
+/// Longest time announcer waits before checking schedule reset
+const MAX_WAIT: Duration = Duration::from_secs(1);
 
 pub fn announcer_task(
-    verbose: bool,
-    ip_config:IpConfig,
-    broadcast: bool,
-    continue_trigger: Arc<AtomicBool>,
+    config: DiscoveryConfig,
+    stop_signal: Arc<StopSignal>,
     base_beacon: Beacon, 
-    period: Duration,
-    socket: UdpSocket,
-    extra_unicast: Vec<SocketAddr>
+    socket: SharedSocket,
+    interfaces: SharedInterfaces,
+    reset: Arc<ScheduleReset>,
+    emitted: SharedBeacon
 ) {
     let mut beacon = base_beacon;
+    let mut schedule = Schedule::new(config.emission, config.period, config.jitter);
 
-    while continue_trigger.load(Ordering::SeqCst) {
+    // First beacon asks neighbours to answer instead of waiting for their next beacon
+    let mut solicit = true;
 
-        let buf = beacon.as_bytes().unwrap();
+    loop {
+        // Advertized period is the actual interval until next beacon
+        let interval = schedule.next_interval();
+        beacon.period = Some(interval);
 
-        if matches!(ip_config, IpConfig::Both) || matches!(ip_config, IpConfig::Ipv6Only) {
+        *emitted.write().unwrap() = beacon.clone();
 
-            let addr = match broadcast {
-                true => "[ff02::1]:3005",
-                false => "[ff02::d4cd:0305:3af1:aeef:75de]:3005",
-            };
+        if solicit {
+            emit(&config, &socket.lock().unwrap(), &interfaces.read().unwrap(), &beacon.solicit());
+            solicit = false;
+        } else {
+            emit(&config, &socket.lock().unwrap(), &interfaces.read().unwrap(), &beacon);
+        }
 
-            match socket.send_to(&buf, addr) {
-                Ok(_) => if verbose { println!("Emitted v6 beacon #{}", beacon.sequence_number) },
-                Err(e) => println!("Error sending v6 beacon : {}", e),
-            }
+        beacon = beacon.next();
+
+        if wait_interval(&stop_signal, &reset, &mut schedule, interval) {
+            break;
         }
+    }
+
+    let goodbye = beacon.goodbye();
 
-        if matches!(ip_config, IpConfig::Both) || matches!(ip_config, IpConfig::Ipv4Only) {
+    if config.verbose {
+        println!("Sending goodbye beacon");
+    }
 
-            let addr = match broadcast {
-                true => "255.255.255.255:3005",
-                false => "224.0.0.108:3005",
-            };
+    emit(&config, &socket.lock().unwrap(), &interfaces.read().unwrap(), &goodbye);
+}
 
-            match socket.send_to(&buf, addr) {
-                Ok(_) => if verbose { println!("Emitted v4 beacon #{}", beacon.sequence_number) },
-                Err(e) => println!("Error sending v4 beacon : {}", e),
-            }
+/// Wait for `interval`, ended early when schedule is reset
+/// Returns true if stop signal is raised
+fn wait_interval(stop_signal: &StopSignal, reset: &ScheduleReset, schedule: &mut Schedule, interval: Duration) -> bool {
+    let deadline = Instant::now() + interval;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return false;
+        }
+
+        if stop_signal.wait(remaining.min(MAX_WAIT)) {
+            return true;
+        }
+
+        if reset.take() && schedule.reset() {
+            return false;
         }
+    }
+}
+
+/// Send a beacon to every destination
+fn emit(config: &DiscoveryConfig, socket: &UdpSocket, interfaces: &[Interface], beacon: &Beacon) {
+    let DiscoveryConfig { verbose, ip_config, broadcast, extra_unicast, destination_port, .. } = config;
+
+    let buf = encode(config, beacon);
+
+    if uses_default_interface(config, interfaces) {
+        emit_on(config, socket, None, &buf, beacon);
+    }
+
+    for interface in interfaces {
+        emit_on(config, socket, Some(interface), &buf, beacon);
+    }
+
+    // Limited broadcast is only used when asked or when subnets are unknown
+    if *broadcast && !matches!(ip_config, IpConfig::Ipv6Only)
+        && (config.limited_broadcast || uses_default_interface(config, interfaces)) {
+
+        let addr = SocketAddr::new(BROADCAST_V4.into(), *destination_port);
+
+        match socket.send_to(&buf, addr) {
+            Ok(_) => if *verbose { println!("Emitted v4 beacon #{} to {}", beacon.sequence_number, addr) },
+            Err(e) => println!("Error sending v4 beacon to {} : {}", addr, e),
+        }
+    }
+
+    for direct in extra_unicast {
+        let addr = [direct.to_owned()];
+        if let Err(e) = socket.send_to(&buf, addr.as_slice()) {
+            eprintln!("Failed to send direct beacon to {direct}: {e}");
+        } else if *verbose {
+            println!("Direct beacon #{} emitted for {}", beacon.sequence_number, direct)
+        }
+    }
+}
+
+/// Encode a beacon, signed with node key then shared key and encrypted with group key if configured
+/// Timestamp is emission time, replies and solicitations are not mistaken for replays
+pub fn encode(config: &DiscoveryConfig, beacon: &Beacon) -> Vec<u8> {
+    let mut beacon = beacon.clone();
 
-        for direct in &extra_unicast {
-            let addr = [direct.to_owned()];
-            if let Err(e) = socket.send_to(&buf, &addr.as_slice()) {
-                eprintln!("Failed to send direct beacon to {direct}: {e}");
-            } else if verbose {
-                println!("Direct beacon #{} emitted for {}", beacon.sequence_number, direct)
+    if config.timestamp {
+        beacon.timestamp = Some(SystemTime::now());
+    }
+
+    if let Some(signer) = &config.signer {
+        beacon = signer.sign(&beacon).unwrap();
+    }
+
+    if let Some(keys) = &config.shared_keys {
+        beacon = keys.sign(&beacon).unwrap();
+    }
+
+    let bytes = beacon.as_bytes().unwrap();
+
+    match &config.group_keys {
+        Some(keys) => keys.seal(&bytes).unwrap(),
+        None => bytes
+    }
+}
+
+/// Send a solicitation on an interface that just came up
+pub fn solicit_on(config: &DiscoveryConfig, socket: &UdpSocket, interface: &Interface, beacon: &Beacon) {
+    let solicitation = beacon.solicit();
+    let buf = encode(config, &solicitation);
+
+    if config.verbose {
+        println!("Sending solicitation on {}", interface.name);
+    }
+
+    emit_on(config, socket, Some(interface), &buf, &solicitation);
+}
+
+/// Send a beacon to multicast groups of an interface, or of default interface if `None`
+fn emit_on(config: &DiscoveryConfig, socket: &UdpSocket, interface: Option<&Interface>, buf: &[u8], beacon: &Beacon) {
+    let DiscoveryConfig { verbose, ip_config, broadcast, destination_port, .. } = config;
+
+    let name = interface.map(|it| it.name.as_str()).unwrap_or("default interface");
+
+    if !matches!(ip_config, IpConfig::Ipv4Only) && interface.is_none_or(|it| !it.ipv6.is_empty()) {
+
+        let group = match broadcast {
+            true => BROADCAST_V6,
+            false => config.multicast_v6,
+        };
+
+        // Scope ID of a link-local multicast address selects outgoing interface
+        let scope_id = interface.map(|it| it.index).unwrap_or(0);
+        let addr = SocketAddrV6::new(group, *destination_port, 0, scope_id);
+
+        match socket.send_to(buf, addr) {
+            Ok(_) => if *verbose { println!("Emitted v6 beacon #{} on {}", beacon.sequence_number, name) },
+            Err(e) => println!("Error sending v6 beacon on {} : {}", name, e),
+        }
+    }
+
+    if *broadcast && !matches!(ip_config, IpConfig::Ipv6Only) {
+        let Some(interface) = interface else {
+            return;
+        };
+
+        for addr in interface.ipv4.iter().filter_map(|it| it.directed_broadcast()) {
+            let addr = SocketAddr::new(addr.into(), *destination_port);
+
+            match socket.send_to(buf, addr) {
+                Ok(_) => if *verbose { println!("Emitted v4 beacon #{} to {} on {}", beacon.sequence_number, addr, name) },
+                Err(e) => println!("Error sending v4 beacon to {} on {} : {}", addr, name, e),
             }
         }
-        
-        beacon = beacon.next();
-        thread::sleep(period);
+    }
+
+    if !broadcast && !matches!(ip_config, IpConfig::Ipv6Only) {
 
+        // Socket is shared, interface selected for a previous emission must not stick to default one
+        let multicast_if = match interface {
+            Some(interface) => match interface.ipv4.first() {
+                Some(v4) => v4.ip,
+                None => return,
+            },
+            None => Ipv4Addr::UNSPECIFIED,
+        };
+
+        if let Err(e) = SockRef::from(socket).set_multicast_if_v4(&multicast_if) {
+            println!("Unable to select {} for v4 multicast : {}", name, e);
+            return;
+        }
+
+        let addr = SocketAddr::new(config.multicast_v4.into(), *destination_port);
+
+        match socket.send_to(buf, addr) {
+            Ok(_) => if *verbose { println!("Emitted v4 beacon #{} on {}", beacon.sequence_number, name) },
+            Err(e) => println!("Error sending v4 beacon on {} : {}", name, e),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,169 @@
+use std::{fmt::Display, net::{SocketAddr, TcpStream}, time::Duration};
+
+use clap::ValueEnum;
+
+use crate::beacon::Service;
+
+/// A convergence layer not accepting a TCP connection within this delay is considered down
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Kind of convergence layer advertized in a beacon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ClaKind {
+    #[value(name="tcpclv4")]
+    TCPCLv4,
+
+    #[value(name="tcpclv3")]
+    TCPCLv3,
+
+    #[value(name="mtcp", alias="mtcpcl")]
+    MTCPCL
+}
+
+impl ClaKind {
+    /// Kind of convergence layer of a service, if it is one
+    pub fn of(service: &Service) -> Option<Self> {
+        match service {
+            Service::TCPCLv4(_) => Some(ClaKind::TCPCLv4),
+            Service::TCPCLv3(_) => Some(ClaKind::TCPCLv3),
+            Service::MTCPCL(_) => Some(ClaKind::MTCPCL),
+            _ => None
+        }
+    }
+}
+
+impl Display for ClaKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClaKind::TCPCLv4 => write!(f, "tcpclv4"),
+            ClaKind::TCPCLv3 => write!(f, "tcpclv3"),
+            ClaKind::MTCPCL => write!(f, "mtcp"),
+        }
+    }
+}
+
+/// How convergence layers of a neighbour are chosen
+#[derive(Debug, Clone, Default)]
+pub struct ClaPolicy {
+    /// Preferred convergence layers, most preferred first
+    /// Convergence layers not listed come after, in neighbour's order
+    pub preference: Vec<ClaKind>,
+
+    /// Fall back to the next convergence layer of a neighbour when preferred ones do not accept connections
+    /// Core keeps a single CLA address per node, failover replaces it
+    pub failover: bool
+}
+
+impl ClaPolicy {
+
+    /// Select service to configure contact through
+    /// Only convergence layers in `available` (supported locally) are considered
+    /// Core keeps a single CLA address per node, so a single service is selected
+    pub fn select<'a>(&self, available: &[ClaKind], services: &'a [Service]) -> Option<&'a Service> {
+        self.candidates(available, services).into_iter().next()
+    }
+
+    /// Services contact can be configured through, most preferred first
+    /// Equally ranked services keep neighbour's order
+    pub fn candidates<'a>(&self, available: &[ClaKind], services: &'a [Service]) -> Vec<&'a Service> {
+        let mut candidates: Vec<(usize, &Service)> = services.iter()
+            .filter_map(|service| ClaKind::of(service)
+                .filter(|kind| available.contains(kind))
+                .map(|kind| (self.rank(kind), service)))
+            .collect();
+
+        candidates.sort_by_key(|(rank, _)| *rank);
+        candidates.into_iter().map(|(_, service)| service).collect()
+    }
+
+    fn rank(&self, kind: ClaKind) -> usize {
+        self.preference.iter()
+            .position(|it| *it == kind)
+            .unwrap_or(self.preference.len())
+    }
+}
+
+/// Does convergence layer `service` of a neighbour heard from `address` accept TCP connections
+/// Every convergence layer runs over TCP, connection is closed right away
+pub fn probe(service: &Service, address: SocketAddr) -> bool {
+    let port = match service {
+        Service::TCPCLv4(port) | Service::TCPCLv3(port) | Service::MTCPCL(port) => *port,
+        _ => return false
+    };
+
+    // Address keeps its scope, link-local neighbours are reached through the interface they were heard on
+    let mut address = address;
+    address.set_port(port);
+
+    TcpStream::connect_timeout(&address, PROBE_TIMEOUT).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+
+    use crate::beacon::Service;
+
+    use super::{probe, ClaKind, ClaPolicy};
+
+    const AVAILABLE: [ClaKind; 3] = [ClaKind::TCPCLv4, ClaKind::TCPCLv3, ClaKind::MTCPCL];
+
+    fn services() -> Vec<Service> {
+        vec![Service::Address("here".into()), Service::MTCPCL(16162), Service::TCPCLv3(4556), Service::TCPCLv4(4557)]
+    }
+
+    #[test]
+    fn follows_local_preference() {
+        let policy = ClaPolicy { preference: vec![ClaKind::TCPCLv4, ClaKind::TCPCLv3], failover: false };
+        let services = services();
+
+        assert!(matches!(policy.select(&AVAILABLE, &services), Some(Service::TCPCLv4(4557))));
+    }
+
+    #[test]
+    fn unlisted_clas_keep_neighbour_order() {
+        let services = services();
+
+        let none = ClaPolicy::default();
+        assert!(matches!(none.select(&AVAILABLE, &services), Some(Service::MTCPCL(16162))));
+
+        let policy = ClaPolicy { preference: vec![ClaKind::TCPCLv3], failover: false };
+        assert!(matches!(policy.select(&AVAILABLE, &services), Some(Service::TCPCLv3(4556))));
+    }
+
+    #[test]
+    fn only_locally_available_clas() {
+        let policy = ClaPolicy { preference: vec![ClaKind::MTCPCL], failover: false };
+        let services = services();
+
+        assert!(matches!(policy.select(&[ClaKind::TCPCLv4], &services), Some(Service::TCPCLv4(4557))));
+        assert!(policy.select(&[], &services).is_none());
+    }
+
+    #[test]
+    fn candidates_are_ranked() {
+        let policy = ClaPolicy { preference: vec![ClaKind::TCPCLv4], failover: true };
+        let services = services();
+
+        let candidates = policy.candidates(&AVAILABLE, &services);
+        assert!(matches!(candidates.as_slice(), [Service::TCPCLv4(4557), Service::MTCPCL(16162), Service::TCPCLv3(4556)]));
+
+        let candidates = policy.candidates(&[ClaKind::TCPCLv3, ClaKind::TCPCLv4], &services);
+        assert!(matches!(candidates.as_slice(), [Service::TCPCLv4(4557), Service::TCPCLv3(4556)]));
+    }
+
+    #[test]
+    fn probes_listening_cla() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Port of the beacon source is replaced by the CLA one
+        let source: SocketAddr = "127.0.0.1:3003".parse().unwrap();
+        assert!(probe(&Service::TCPCLv4(port), source));
+
+        drop(listener);
+        assert!(!probe(&Service::TCPCLv4(port), source));
+        assert!(!probe(&Service::Address("here".into()), source));
+    }
+}
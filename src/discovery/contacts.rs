@@ -0,0 +1,154 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::beacon::NodeIdentifier;
+
+/// Number of neighbour periods a configured contact lasts
+pub const CONTACT_LIFETIME_PERIODS: u32 = 6;
+
+/// A contact is refreshed when it expires in less than this number of periods
+pub const REFRESH_BEFORE_PERIODS: u32 = 3;
+
+/// A contact configured in core for a neighbour
+/// Core keeps a single CLA address per node, a new one replaces the previous one
+#[derive(Debug, Clone)]
+pub struct ConfiguredContact {
+    /// CLA address the contact goes through
+    pub cla_address: String,
+
+    /// End of configured contact
+    pub until: Instant
+}
+
+/// What to send to core for a neighbour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactAction {
+    /// No contact is configured yet
+    Add,
+
+    /// Configured contact changed or is about to expire
+    Replace,
+
+    /// Configured contact is still valid
+    Keep
+}
+
+/// Contacts configured in core, by neighbour
+#[derive(Debug, Default)]
+pub struct ContactTable {
+    contacts: HashMap<NodeIdentifier, ConfiguredContact>,
+
+    /// Number of config bundles sent to core
+    pub bundles_sent: u64,
+
+    /// Number of config bundles not sent because contact was still valid
+    pub bundles_saved: u64
+}
+
+impl ContactTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decide what to send to core for a neighbour reachable through `cla_address`
+    /// `changed` forces a refresh when neighbour services or addresses changed
+    pub fn plan(&mut self, node_id: &NodeIdentifier, cla_address: &str, period: Duration, changed: bool, now: Instant)
+        -> ContactAction {
+        let Some(contact) = self.contacts.get(node_id) else {
+            return ContactAction::Add;
+        };
+
+        if changed
+            || contact.cla_address != cla_address
+            || contact.until <= now + period * REFRESH_BEFORE_PERIODS {
+            return ContactAction::Replace;
+        }
+
+        self.bundles_saved += 1;
+        ContactAction::Keep
+    }
+
+    /// CLA address of contact configured for a neighbour
+    pub fn cla_address(&self, node_id: &NodeIdentifier) -> Option<&str> {
+        self.contacts.get(node_id).map(|contact| contact.cla_address.as_str())
+    }
+
+    /// Record a contact sent to core
+    pub fn configured(&mut self, node_id: NodeIdentifier, cla_address: String, until: Instant) {
+        self.contacts.insert(node_id, ConfiguredContact { cla_address, until });
+    }
+
+    /// Forget contact of a neighbour
+    /// Returns true if a contact was configured
+    pub fn remove(&mut self, node_id: &NodeIdentifier) -> bool {
+        self.contacts.remove(node_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::testing::NODE_ID;
+
+    use super::{ContactAction, ContactTable, CONTACT_LIFETIME_PERIODS, REFRESH_BEFORE_PERIODS};
+
+    const PERIOD: Duration = Duration::from_secs(30);
+
+    fn cla(ip: &str) -> String {
+        format!("tcpclv4:{}:4556", ip)
+    }
+
+    #[test]
+    fn adds_then_keeps_valid_contact() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut contacts = ContactTable::new();
+
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, false, now), ContactAction::Add);
+        contacts.configured(node_id.clone(), cla("192.0.2.1"), now + PERIOD * CONTACT_LIFETIME_PERIODS);
+
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, false, now + PERIOD), ContactAction::Keep);
+        assert_eq!(contacts.bundles_saved, 1);
+    }
+
+    #[test]
+    fn replaces_changed_contact() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut contacts = ContactTable::new();
+
+        contacts.configured(node_id.clone(), cla("192.0.2.1"), now + PERIOD * CONTACT_LIFETIME_PERIODS);
+
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, true, now), ContactAction::Replace);
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.2"), PERIOD, false, now), ContactAction::Replace);
+        assert_eq!(contacts.bundles_saved, 0);
+    }
+
+    #[test]
+    fn replaces_contact_about_to_expire() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let until = now + PERIOD * CONTACT_LIFETIME_PERIODS;
+        let refresh_at = until - PERIOD * REFRESH_BEFORE_PERIODS;
+        let mut contacts = ContactTable::new();
+
+        contacts.configured(node_id.clone(), cla("192.0.2.1"), until);
+
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, false, refresh_at - Duration::from_secs(1)),
+            ContactAction::Keep);
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, false, refresh_at), ContactAction::Replace);
+    }
+
+    #[test]
+    fn removed_contact_added_again() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut contacts = ContactTable::new();
+
+        contacts.configured(node_id.clone(), cla("192.0.2.1"), now + PERIOD * CONTACT_LIFETIME_PERIODS);
+
+        assert!(contacts.remove(&node_id));
+        assert!(!contacts.remove(&node_id));
+        assert_eq!(contacts.plan(&node_id, &cla("192.0.2.1"), PERIOD, false, now), ContactAction::Add);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::{BTreeMap, HashSet}, io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}};
+
+use if_addrs::IfAddr;
+use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags};
+
+/// An ipv4 address of a network interface
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InterfaceV4Addr {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub broadcast: Option<Ipv4Addr>
+}
+
+impl InterfaceV4Addr {
+    /// Directed broadcast address of this address subnet
+    /// None for /31 and /32 subnets that have no broadcast address
+    pub fn directed_broadcast(&self) -> Option<Ipv4Addr> {
+        if self.netmask.to_bits().count_zeros() < 2 {
+            return None;
+        }
+
+        Some(self.broadcast.unwrap_or(
+            Ipv4Addr::from_bits(self.ip.to_bits() | !self.netmask.to_bits())))
+    }
+}
+
+/// An ipv6 address of a network interface
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InterfaceV6Addr {
+    pub ip: Ipv6Addr,
+    pub netmask: Ipv6Addr
+}
+
+/// A network interface beacons are emitted and received on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub ipv4: Vec<InterfaceV4Addr>,
+    pub ipv6: Vec<InterfaceV6Addr>
+}
+
+impl Interface {
+    /// Is `addr` directly reachable through this interface
+    pub fn reaches(&self, addr: &SocketAddr) -> bool {
+        match addr {
+            SocketAddr::V6(v6) if v6.ip().is_unicast_link_local() => v6.scope_id() == self.index,
+            _ => match addr.ip().to_canonical() {
+                IpAddr::V4(ip) => self.ipv4.iter().any(|it|
+                    ip.to_bits() & it.netmask.to_bits() == it.ip.to_bits() & it.netmask.to_bits()),
+                IpAddr::V6(ip) => self.ipv6.iter().any(|it|
+                    ip.to_bits() & it.netmask.to_bits() == it.ip.to_bits() & it.netmask.to_bits()),
+            }
+        }
+    }
+}
+
+/// Interfaces discovery runs on
+/// Interface names can end with `*` to match every interface starting with it
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceFilter {
+    /// Only use these interfaces, every non-loopback interface if empty
+    pub allow: Vec<String>,
+
+    /// Never use these interfaces
+    pub deny: Vec<String>
+}
+
+impl InterfaceFilter {
+    /// Is every non-loopback interface accepted
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn accepts(&self, name: &str, is_loopback: bool) -> bool {
+        if self.deny.iter().any(|it| name_matches(it, name)) {
+            return false;
+        }
+
+        if self.allow.is_empty() {
+            !is_loopback
+        } else {
+            self.allow.iter().any(|it| name_matches(it, name))
+        }
+    }
+}
+
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name
+    }
+}
+
+/// List interfaces of this host accepted by `filter`
+/// Interfaces down or without carrier are left out, even if they kept their addresses
+pub fn list_interfaces(filter: &InterfaceFilter) -> io::Result<Vec<Interface>> {
+    let mut interfaces: BTreeMap<String, Interface> = BTreeMap::new();
+    let running = running_interfaces()?;
+
+    for addr in if_addrs::get_if_addrs()? {
+        if !running.contains(&addr.name) || !filter.accepts(&addr.name, addr.is_loopback()) {
+            continue;
+        }
+
+        let Some(index) = addr.index else {
+            continue;
+        };
+
+        let interface = interfaces.entry(addr.name.clone())
+            .or_insert_with(|| Interface {
+                name: addr.name.clone(),
+                index,
+                ipv4: Vec::new(),
+                ipv6: Vec::new()
+            });
+
+        match addr.addr {
+            IfAddr::V4(v4) => interface.ipv4.push(InterfaceV4Addr {
+                ip: v4.ip,
+                netmask: v4.netmask,
+                broadcast: v4.broadcast
+            }),
+            IfAddr::V6(v6) => interface.ipv6.push(InterfaceV6Addr {
+                ip: v6.ip,
+                netmask: v6.netmask
+            }),
+        }
+    }
+
+    Ok(interfaces.into_values().collect())
+}
+
+/// Names of interfaces administratively up and with their link running
+fn running_interfaces() -> io::Result<HashSet<String>> {
+    let running = InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING;
+
+    Ok(getifaddrs()?
+        .filter(|it| it.flags.contains(running))
+        .map(|it| it.interface_name)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, SocketAddrV6};
+
+    use super::{Interface, InterfaceFilter, InterfaceV4Addr, InterfaceV6Addr};
+
+    fn v4(ip: &str, netmask: &str) -> InterfaceV4Addr {
+        InterfaceV4Addr { ip: ip.parse().unwrap(), netmask: netmask.parse().unwrap(), broadcast: None }
+    }
+
+    #[test]
+    fn filter_interfaces() {
+        let every = InterfaceFilter::default();
+        assert!(every.accepts("eth0", false));
+        assert!(!every.accepts("lo", true));
+
+        let filter = InterfaceFilter { allow: vec!["eth*".into(), "lo".into()], deny: vec!["eth1".into()] };
+        assert!(filter.accepts("eth0", false));
+        assert!(filter.accepts("lo", true));
+        assert!(!filter.accepts("eth1", false));
+        assert!(!filter.accepts("wlan0", false));
+    }
+
+    #[test]
+    fn reaches_addresses_of_its_subnets() {
+        let interface = Interface {
+            name: "eth0".into(),
+            index: 2,
+            ipv4: vec![v4("192.0.2.10", "255.255.255.0")],
+            ipv6: vec![InterfaceV6Addr { ip: "2001:db8::10".parse().unwrap(), netmask: "ffff:ffff:ffff:ffff::".parse().unwrap() }]
+        };
+
+        let reaches = |addr: &str| interface.reaches(&addr.parse().unwrap());
+
+        assert!(reaches("192.0.2.1:3003"));
+        assert!(reaches("[::ffff:192.0.2.1]:3003"));
+        assert!(!reaches("198.51.100.1:3003"));
+        assert!(reaches("[2001:db8::1]:3003"));
+        assert!(!reaches("[2001:db8:1::1]:3003"));
+
+        // Link-local addresses are reached through the interface of their scope
+        let link_local = |scope_id| SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 3003, 0, scope_id));
+        assert!(interface.reaches(&link_local(2)));
+        assert!(!interface.reaches(&link_local(3)));
+    }
+
+    #[test]
+    fn directed_broadcast_of_subnet() {
+        assert_eq!(v4("192.0.2.10", "255.255.255.0").directed_broadcast(), Some("192.0.2.255".parse().unwrap()));
+        assert_eq!(v4("10.1.2.3", "255.0.0.0").directed_broadcast(), Some("10.255.255.255".parse().unwrap()));
+        assert_eq!(v4("192.0.2.9", "255.255.255.252").directed_broadcast(), Some("192.0.2.11".parse().unwrap()));
+
+        // Broadcast address reported by the interface wins
+        let reported = InterfaceV4Addr { broadcast: Some("192.0.2.127".parse().unwrap()), ..v4("192.0.2.10", "255.255.255.0") };
+        assert_eq!(reported.directed_broadcast(), Some("192.0.2.127".parse().unwrap()));
+
+        // Point to point and single host subnets have no broadcast address
+        assert_eq!(v4("192.0.2.10", "255.255.255.254").directed_broadcast(), None);
+        assert_eq!(v4("192.0.2.10", "255.255.255.255").directed_broadcast(), None);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, time::{Duration, Instant}};
+
+use crate::beacon::NodeIdentifier;
+
+/// Default largest datagram accepted as a beacon
+pub const DEFAULT_MAX_BEACON_SIZE: usize = 4096;
+
+/// Burst allowed by a rate limit, in seconds of rate
+const BURST_SECONDS: f64 = 5.0;
+
+/// Idle buckets are forgotten at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of events allowed per second, with bursts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Events per second in the long run
+    pub rate: f64,
+
+    /// Events allowed at once
+    pub burst: f64
+}
+
+impl RateLimit {
+    /// Allow `rate` events per second and bursts of a few seconds of rate
+    pub fn per_second(rate: f64) -> Self {
+        Self { rate, burst: (rate * BURST_SECONDS).max(1.0) }
+    }
+}
+
+/// Limits protecting receiver and core from misbehaving or hostile nodes
+#[derive(Debug, Clone)]
+pub struct ReceiveLimits {
+    /// Largest datagram accepted as a beacon
+    pub max_beacon_size: usize,
+
+    /// Datagrams accepted from a single ip address
+    pub per_source: RateLimit,
+
+    /// Beacons accepted from a single node id
+    pub per_node: RateLimit,
+
+    /// Config bundles sent to core
+    pub config_bundles: RateLimit
+}
+
+impl Default for ReceiveLimits {
+    fn default() -> Self {
+        Self {
+            max_beacon_size: DEFAULT_MAX_BEACON_SIZE,
+            per_source: RateLimit::per_second(5.0),
+            per_node: RateLimit::per_second(10.0),
+            config_bundles: RateLimit::per_second(10.0)
+        }
+    }
+}
+
+/// Token bucket, refilled at `rate` tokens per second up to `burst`
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self { tokens: limit.burst, updated: now }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    fn try_take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Token buckets of every source, keyed by `K`
+#[derive(Debug)]
+struct Buckets<K> {
+    limit: RateLimit,
+    buckets: HashMap<K, TokenBucket>
+}
+
+impl<K: Hash + Eq> Buckets<K> {
+    fn new(limit: RateLimit) -> Self {
+        Self { limit, buckets: HashMap::new() }
+    }
+
+    fn try_take(&mut self, key: K, now: Instant) -> bool {
+        self.buckets.entry(key)
+            .or_insert_with(|| TokenBucket::new(&self.limit, now))
+            .try_take(&self.limit, now)
+    }
+
+    /// Forget full buckets, they behave like new ones
+    fn prune(&mut self, now: Instant) {
+        let limit = self.limit;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(&limit, now);
+            bucket.tokens < limit.burst
+        });
+    }
+}
+
+/// Applies receive limits and counts dropped packets by cause
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    max_beacon_size: usize,
+    sources: Buckets<IpAddr>,
+    nodes: Buckets<NodeIdentifier>,
+    config_bundles: TokenBucket,
+    config_bundles_limit: RateLimit,
+    last_prune: Instant,
+
+    /// Number of dropped packets and config bundles by cause
+    pub dropped: HashMap<&'static str, u64>
+}
+
+impl Limiter {
+    pub fn new(limits: &ReceiveLimits) -> Self {
+        let now = Instant::now();
+
+        Self {
+            max_beacon_size: limits.max_beacon_size,
+            sources: Buckets::new(limits.per_source),
+            nodes: Buckets::new(limits.per_node),
+            config_bundles: TokenBucket::new(&limits.config_bundles, now),
+            config_bundles_limit: limits.config_bundles,
+            last_prune: now,
+            dropped: HashMap::new()
+        }
+    }
+
+    /// Size of receive buffer, one more byte than accepted to detect larger datagrams
+    pub fn buffer_size(&self) -> usize {
+        self.max_beacon_size + 1
+    }
+
+    /// Check size and source rate of a received datagram before parsing it
+    pub fn accept_datagram(&mut self, size: usize, source: IpAddr, now: Instant) -> bool {
+        if size > self.max_beacon_size {
+            return self.drop("beacon too large");
+        }
+
+        self.prune(now);
+
+        if !self.sources.try_take(source.to_canonical(), now) {
+            return self.drop("source rate exceeded");
+        }
+
+        true
+    }
+
+    /// Check rate of beacons advertizing `node_id`
+    pub fn accept_node(&mut self, node_id: &NodeIdentifier, now: Instant) -> bool {
+        if !self.nodes.try_take(node_id.clone(), now) {
+            return self.drop("node rate exceeded");
+        }
+
+        true
+    }
+
+    /// Check global config bundles rate before sending one to core
+    pub fn accept_config_bundle(&mut self, now: Instant) -> bool {
+        if !self.config_bundles.try_take(&self.config_bundles_limit, now) {
+            return self.drop("config bundle rate exceeded");
+        }
+
+        true
+    }
+
+    fn drop(&mut self, cause: &'static str) -> bool {
+        *self.dropped.entry(cause).or_default() += 1;
+        false
+    }
+
+    fn prune(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_prune) < PRUNE_INTERVAL {
+            return;
+        }
+
+        self.sources.prune(now);
+        self.nodes.prune(now);
+        self.last_prune = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::{Duration, Instant}};
+
+    use super::{Limiter, RateLimit, ReceiveLimits, TokenBucket};
+
+    #[test]
+    fn bucket_allows_burst_then_rate() {
+        let limit = RateLimit { rate: 2.0, burst: 3.0 };
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&limit, now);
+
+        assert!((0..3).all(|_| bucket.try_take(&limit, now)));
+        assert!(!bucket.try_take(&limit, now));
+
+        // Two tokens per second
+        assert!(bucket.try_take(&limit, now + Duration::from_millis(500)));
+        assert!(!bucket.try_take(&limit, now + Duration::from_millis(500)));
+
+        // Refill stops at burst
+        let later = now + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_take(&limit, later)));
+        assert!(!bucket.try_take(&limit, later));
+    }
+
+    #[test]
+    fn limits_each_source_separately() {
+        let limits = ReceiveLimits { per_source: RateLimit { rate: 1.0, burst: 1.0 }, ..ReceiveLimits::default() };
+        let mut limiter = Limiter::new(&limits);
+        let now = Instant::now();
+
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert!(limiter.accept_datagram(100, a, now));
+        assert!(!limiter.accept_datagram(100, a, now));
+        assert!(limiter.accept_datagram(100, b, now));
+
+        // Mapped ipv4 shares bucket of ipv4 address
+        assert!(!limiter.accept_datagram(100, "::ffff:192.0.2.1".parse().unwrap(), now));
+
+        assert_eq!(limiter.dropped.get("source rate exceeded"), Some(&2));
+    }
+
+    #[test]
+    fn rejects_oversize_datagram() {
+        let mut limiter = Limiter::new(&ReceiveLimits { max_beacon_size: 100, ..ReceiveLimits::default() });
+        let source: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert_eq!(limiter.buffer_size(), 101);
+        assert!(limiter.accept_datagram(100, source, Instant::now()));
+        assert!(!limiter.accept_datagram(101, source, Instant::now()));
+        assert_eq!(limiter.dropped.get("beacon too large"), Some(&1));
+    }
+
+    #[test]
+    fn caps_config_bundles() {
+        let limits = ReceiveLimits { config_bundles: RateLimit { rate: 1.0, burst: 2.0 }, ..ReceiveLimits::default() };
+        let mut limiter = Limiter::new(&limits);
+        let now = Instant::now();
+
+        assert!(limiter.accept_config_bundle(now));
+        assert!(limiter.accept_config_bundle(now));
+        assert!(!limiter.accept_config_bundle(now));
+        assert!(limiter.accept_config_bundle(now + Duration::from_secs(1)));
+    }
+}
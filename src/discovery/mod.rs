@@ -1,97 +1,437 @@
-use std::{net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}, str::FromStr, sync::{atomic::AtomicBool, Arc}, thread, time::Duration};
+use std::{io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}, os::unix::net::UnixStream, sync::{mpsc::{self, Sender}, Arc, Mutex, RwLock}, thread::{self, JoinHandle}, time::Duration};
 
 use ud3tn_aap::{AapStream, RegisteredAgent};
 
-use crate::{beacon::{Beacon, NodeIdentifier}, IpConfig};
-use std::sync::atomic::Ordering;
+use crate::beacon::{auth::SharedKeys, envelope::GroupKeys, signature::BeaconSigner, Beacon, NodeIdentifier};
 
 mod announcer;
 mod receiver;
+mod contacts;
+mod stop;
+mod watcher;
+mod address;
+mod cla;
+mod interfaces;
+mod limits;
+mod neighbours;
+mod node_filter;
+mod replay;
+mod schedule;
+mod trust;
 
+pub use address::{AddressPolicy, FamilyPreference, ScopePreference};
+pub use cla::{ClaKind, ClaPolicy};
+pub use interfaces::InterfaceFilter;
+pub use limits::{RateLimit, ReceiveLimits, DEFAULT_MAX_BEACON_SIZE};
+pub use neighbours::{Neighbour, NeighbourEvent, NeighbourState};
+pub use node_filter::{EidPattern, NodeFilter};
+pub use schedule::EmissionMode;
+pub use trust::TrustStore;
+
+use interfaces::{list_interfaces, Interface};
+use schedule::{ScheduleReset, DEFAULT_JITTER};
+use stop::StopSignal;
+
+/// Default port beacons are emitted to and received on
+pub const DEFAULT_PORT: u16 = 3005;
+
+/// Default ipv4 multicast group
+pub const DEFAULT_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 108);
+
+/// Default ipv6 multicast group
+pub const DEFAULT_MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0xd4cd, 0x0305, 0x3af1, 0xaeef, 0x75de);
+
+/// Ipv4 limited broadcast address
+pub const BROADCAST_V4: Ipv4Addr = Ipv4Addr::BROADCAST;
+
+/// Ipv6 all nodes link-local multicast group, used in broadcast mode
+pub const BROADCAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// Interfaces currently in use, updated when interfaces change
+type SharedInterfaces = Arc<RwLock<Vec<Interface>>>;
+
+/// Socket beacons are multicast on, locked while outgoing interface is selected and beacon sent
+/// so announcer and watcher do not send through each other's interface
+type SharedSocket = Arc<Mutex<UdpSocket>>;
+
+/// Last beacon emitted by announcer, sent again by receiver to reply to new neighbours
+type SharedBeacon = Arc<RwLock<Beacon>>;
+
+/// IP families used to listen and emit beacons
+#[derive(Debug, Clone)]
+pub enum IpConfig {
+    Ipv4Only,
+    Ipv6Only,
+    Both
+}
+
+/// Configuration of a discovery session
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// Log more
+    pub verbose: bool,
+
+    /// IP families to listen and emit on
+    pub ip_config: IpConfig,
+
+    /// Broadcast beacons instead of multicast
+    /// Ipv4 beacons are sent to the directed broadcast address of each subnet
+    pub broadcast: bool,
+
+    /// Also send ipv4 beacons to limited broadcast (255.255.255.255) in broadcast mode
+    pub limited_broadcast: bool,
+
+    /// Duration between two advertizments
+    /// Longest interval in trickle mode
+    pub period: Duration,
+
+    /// How intervals between two advertizments are chosen
+    pub emission: EmissionMode,
+
+    /// Random variation of intervals, as a fraction of the interval (0 to 1)
+    pub jitter: f64,
+
+    /// Additionnal addresses beacons are sent to in unicast
+    pub extra_unicast: Vec<SocketAddr>,
+
+    /// Port beacons are received on
+    pub listen_port: u16,
+
+    /// Port beacons are emitted to in broadcast and multicast
+    pub destination_port: u16,
+
+    /// Ipv4 multicast group beacons are emitted to and received from
+    pub multicast_v4: Ipv4Addr,
+
+    /// Ipv6 multicast group beacons are emitted to and received from
+    pub multicast_v6: Ipv6Addr,
+
+    /// Interfaces beacons are emitted and received on
+    pub interfaces: InterfaceFilter,
+
+    /// Reject received beacons with unknown flags or extra fields
+    pub strict: bool,
+
+    /// Node ids neighbours are accepted from
+    pub node_filter: NodeFilter,
+
+    /// Only listen and configure contacts, never emit beacons
+    pub passive: bool,
+
+    /// Limits protecting receiver and core from flooding
+    pub limits: ReceiveLimits,
+
+    /// Sign emitted beacons and drop received beacons without a valid MAC when set
+    pub shared_keys: Option<SharedKeys>,
+
+    /// Sign emitted beacons with the key of this node when set
+    pub signer: Option<BeaconSigner>,
+
+    /// Drop received beacons not signed by a key trusted for their node when set
+    pub trust_store: Option<TrustStore>,
+
+    /// Add emission time to emitted beacons
+    pub timestamp: bool,
+
+    /// Encrypt emitted beacons and only accept encrypted beacons when set
+    pub group_keys: Option<GroupKeys>,
+
+    /// Drop received beacons without timestamp, with a timestamp further than this from now,
+    /// or older than the last one of their node when set
+    pub replay_window: Option<Duration>,
+
+    /// Neighbour events are sent to this channel when set
+    pub events: Option<Sender<NeighbourEvent>>,
+
+    /// How convergence layers of neighbours are chosen
+    pub cla_policy: ClaPolicy,
+
+    /// How addresses of neighbours are chosen
+    pub address_policy: AddressPolicy
+}
+
+impl DiscoveryConfig {
+
+    /// Create a new discovery configuration with default values
+    pub fn new() -> Self {
+        Self {
+            verbose: false,
+            ip_config: IpConfig::Both,
+            broadcast: false,
+            limited_broadcast: false,
+            period: Duration::from_secs(30),
+            emission: EmissionMode::Fixed,
+            jitter: DEFAULT_JITTER,
+            extra_unicast: Vec::new(),
+            listen_port: DEFAULT_PORT,
+            destination_port: DEFAULT_PORT,
+            multicast_v4: DEFAULT_MULTICAST_V4,
+            multicast_v6: DEFAULT_MULTICAST_V6,
+            interfaces: InterfaceFilter::default(),
+            strict: false,
+            node_filter: NodeFilter::default(),
+            passive: false,
+            limits: ReceiveLimits::default(),
+            shared_keys: None,
+            signer: None,
+            trust_store: None,
+            timestamp: false,
+            group_keys: None,
+            replay_window: None,
+            events: None,
+            cla_policy: ClaPolicy::default(),
+            address_policy: AddressPolicy::default()
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A running discovery session
+/// Announcer and receiver run in their own threads until stopped
+pub struct DiscoveryHandle {
+    stopper: DiscoveryStopper,
+
+    /// No announcer runs in passive mode
+    announcer: Option<JoinHandle<()>>,
+    receiver: JoinHandle<()>,
+    watcher: JoinHandle<()>
+}
+
+impl DiscoveryHandle {
+
+    /// Get a stopper that can end this session from another thread
+    pub fn stopper(&self) -> DiscoveryStopper {
+        self.stopper.clone()
+    }
+
+    /// Ask announcer and receiver to stop
+    /// Announcer sends a goodbye beacon before ending
+    pub fn stop(&self) {
+        self.stopper().stop()
+    }
+
+    /// Wait for announcer and receiver to end
+    pub fn join(self) {
+        if self.receiver.join().is_err() {
+            eprintln!("Receiver task panicked");
+        }
+        if self.announcer.is_some_and(|it| it.join().is_err()) {
+            eprintln!("Announcer task panicked");
+        }
+        if self.watcher.join().is_err() {
+            eprintln!("Interface watcher task panicked");
+        }
+    }
+}
+
+/// Stops a running discovery session
+#[derive(Debug, Clone)]
+pub struct DiscoveryStopper {
+    stop_signal: Arc<StopSignal>,
+
+    /// Local address of discovery socket, an empty datagram sent there wakes the receiver up
+    wake_addr: SocketAddr
+}
+
+impl DiscoveryStopper {
+    pub fn stop(&self) {
+        self.stop_signal.stop();
+        wake(self.wake_addr);
+    }
+}
+
+/// Wake receiver up by sending an empty datagram to its local address
+fn wake(wake_addr: SocketAddr) {
+    let bind_addr = match wake_addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let woken = UdpSocket::bind(bind_addr)
+        .and_then(|socket| socket.send_to(&[], wake_addr));
+
+    if let Err(e) = woken {
+        eprintln!("Unable to wake receiver up : {}", e);
+    }
+}
+
+/// Start announcing `base_beacon` without configuring contacts (announce-only)
+/// Neighbours are still tracked and reported to `DiscoveryConfig::events`
+pub fn start_announcing(config: DiscoveryConfig, base_beacon: Beacon, node_id: NodeIdentifier) -> io::Result<DiscoveryHandle> {
+    start_discovery(config, base_beacon, node_id, None::<RegisteredAgent<UnixStream>>)
+}
+
+/// Start announcing `base_beacon` and configuring neighbours of `node_id` in `aap`
+/// Nothing is announced in passive mode, contacts are not configured if `aap` is `None` (see `start_announcing`)
+/// Fails if discovery socket can't be bound or configured
 pub fn start_discovery(
-    verbose: bool,
-    ip_config: IpConfig,
-    broadcast: bool,
+    config: DiscoveryConfig,
     base_beacon: Beacon,
-    period: Duration,
     node_id: NodeIdentifier,
-    aap: RegisteredAgent<impl AapStream>,
-    extra_unicast: Vec<SocketAddr>
-){
-    let continue_trigger = Arc::new(AtomicBool::new(true));
-
-    let ctrigger_int = continue_trigger.clone();
-    ctrlc::set_handler(move || {
-        println!("Shuttng down");
-        ctrigger_int.store(false, Ordering::SeqCst)
-    }).unwrap();
-
-    let bind_addr = match ip_config {
-        IpConfig::Ipv4Only => "0.0.0.0:3005",
-        IpConfig::Both => "[::]:3005",
-        IpConfig::Ipv6Only => "[::]:3005",
+    aap: Option<RegisteredAgent<impl AapStream + Send + 'static>>
+) -> io::Result<DiscoveryHandle> {
+    let stop_signal = Arc::new(StopSignal::new());
+
+    let bind_addr = match config.ip_config {
+        IpConfig::Ipv4Only => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), config.listen_port),
+        IpConfig::Both => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), config.listen_port),
+        IpConfig::Ipv6Only => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), config.listen_port),
     };
 
-    let socket = UdpSocket::bind(bind_addr)
-        .unwrap_or_else(|_| panic!("Unable to bind v6 socket to {}", bind_addr));
-
-    socket.set_broadcast(true)
-        .expect("Unable to allow socket to broadcast");
-
-    match ip_config {
-        IpConfig::Ipv4Only => {
-            socket.set_multicast_loop_v4(false)
-                .expect("Unable to disable multicast loop v4");
-        },
-        IpConfig::Ipv6Only => {
-            socket.set_multicast_loop_v6(false)
-            .expect("Unable to disable multicast loop v6");
-        },
-        IpConfig::Both => {
-            socket.set_multicast_loop_v4(false)
-                .expect("Unable to disable multicast loop v4");
-            socket.set_multicast_loop_v6(false)
-            .expect("Unable to disable multicast loop v6");
-        },
+    let socket = UdpSocket::bind(bind_addr)?;
+
+    socket.set_broadcast(true)?;
+
+    if !matches!(config.ip_config, IpConfig::Ipv6Only) {
+        socket.set_multicast_loop_v4(false)?;
     }
 
-    if let Err(e) = socket.join_multicast_v4(
-        &Ipv4Addr::from_str("224.0.0.108").unwrap(),&Ipv4Addr::UNSPECIFIED) {
-        println!("Unable to join ipv4 multicast group : {}", e)
+    if !matches!(config.ip_config, IpConfig::Ipv4Only) {
+        socket.set_multicast_loop_v6(false)?;
     }
 
-    if let Err(e) = socket.join_multicast_v6(
-        &Ipv6Addr::from_str("ff02::d4cd:0305:3af1:aeef:75de").unwrap(),0) {
-        println!("Unable to join ipv6 multicast group : {}", e)
+    let interfaces = match list_interfaces(&config.interfaces) {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            println!("Unable to list network interfaces : {}", e);
+            Vec::new()
+        }
+    };
+
+    if uses_default_interface(&config, &interfaces) {
+        join_groups(&config, &socket, None);
+    } else if interfaces.is_empty() {
+        println!("No allowed interface available, waiting for one to come up");
     }
 
+    for interface in &interfaces {
+        if config.verbose {
+            println!("Using interface {}", interface.name);
+        }
+        join_groups(&config, &socket, Some(interface));
+    }
+
+    let loopback = match config.ip_config {
+        IpConfig::Ipv4Only => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        _ => IpAddr::V6(Ipv6Addr::LOCALHOST),
+    };
+
+    let wake_addr = SocketAddr::new(loopback, config.listen_port);
+
+    let stopper = DiscoveryStopper {
+        stop_signal: stop_signal.clone(),
+        wake_addr
+    };
+
+    let interfaces: SharedInterfaces = Arc::new(RwLock::new(interfaces));
+    let (changes_send, changes_recv) = mpsc::channel();
+    let reset = Arc::new(ScheduleReset::new());
+
     println!("Starting discovery");
-    
-    let emitted_beacon = base_beacon.clone();
-
-    let verbose_emit = verbose;
-    let ip_config_emit = ip_config.clone();
-    let ctrigger_emit = continue_trigger.clone();
-    let socket_emit = socket.try_clone().unwrap();
-    thread::spawn(move || announcer::announcer_task(
-        verbose_emit,
-        ip_config_emit,
-        broadcast,
-        ctrigger_emit,
+
+    let emitted_beacon: SharedBeacon = Arc::new(RwLock::new(base_beacon.clone()));
+
+    let config_emit = config.clone();
+    let stop_emit = stop_signal.clone();
+    let shared_socket: SharedSocket = Arc::new(Mutex::new(socket.try_clone()?));
+
+    let socket_emit = shared_socket.clone();
+    let interfaces_emit = interfaces.clone();
+    let reset_emit = reset.clone();
+    let emitted_emit = emitted_beacon.clone();
+    let announcer = (!config.passive).then(|| thread::spawn(move || announcer::announcer_task(
+        config_emit,
+        stop_emit,
         base_beacon,
-        period,
         socket_emit,
-        extra_unicast
+        interfaces_emit,
+        reset_emit,
+        emitted_emit
+    )));
+
+    let config_watch = config.clone();
+    let stop_watch = stop_signal.clone();
+    let socket_watch = shared_socket;
+    let reset_watch = reset.clone();
+    let emitted_watch = emitted_beacon.clone();
+    let watcher = thread::spawn(move || watcher::watcher_task(
+        config_watch,
+        stop_watch,
+        socket_watch,
+        interfaces,
+        changes_send,
+        wake_addr,
+        reset_watch,
+        emitted_watch
     ));
 
-    receiver::receiver_task(
-        verbose, 
-        ip_config,
-        continue_trigger, 
+    let receiver = thread::spawn(move || receiver::receiver_task(
+        config,
+        stop_signal,
         socket,
         node_id,
         aap,
-        emitted_beacon
-    )
+        emitted_beacon,
+        changes_recv,
+        reset
+    ));
+
+    Ok(DiscoveryHandle { stopper, announcer, receiver, watcher })
+}
+
+/// Is the default interface used, when no interface is known and none is filtered out
+/// With an interface filter, nothing is sent until an allowed interface comes up
+fn uses_default_interface(config: &DiscoveryConfig, interfaces: &[Interface]) -> bool {
+    interfaces.is_empty() && config.interfaces.is_empty()
+}
 
-}
\ No newline at end of file
+/// Join multicast groups on an interface, or on default interface if `None`
+fn join_groups(config: &DiscoveryConfig, socket: &UdpSocket, interface: Option<&Interface>) {
+    let name = interface.map(|it| it.name.as_str()).unwrap_or("default interface");
+
+    if !matches!(config.ip_config, IpConfig::Ipv6Only) {
+        let addr = match interface {
+            Some(interface) => interface.ipv4.first().map(|it| it.ip),
+            None => Some(Ipv4Addr::UNSPECIFIED),
+        };
+
+        if let Some(addr) = addr {
+            if let Err(e) = socket.join_multicast_v4(&config.multicast_v4, &addr) {
+                println!("Unable to join ipv4 multicast group {} on {} : {}", config.multicast_v4, name, e)
+            }
+        }
+    }
+
+    if !matches!(config.ip_config, IpConfig::Ipv4Only) {
+        let index = interface.map(|it| it.index).unwrap_or(0);
+
+        if let Err(e) = socket.join_multicast_v6(&config.multicast_v6, index) {
+            println!("Unable to join ipv6 multicast group {} on {} : {}", config.multicast_v6, name, e)
+        }
+    }
+}
+
+/// Leave multicast groups of an interface, or of default interface if `None`
+fn leave_groups(config: &DiscoveryConfig, socket: &UdpSocket, interface: Option<&Interface>) {
+    // Interface may already be gone, membership is then dropped by the kernel
+    if !matches!(config.ip_config, IpConfig::Ipv6Only) {
+        let addr = match interface {
+            Some(interface) => interface.ipv4.first().map(|it| it.ip),
+            None => Some(Ipv4Addr::UNSPECIFIED),
+        };
+
+        if let Some(addr) = addr {
+            let _ = socket.leave_multicast_v4(&config.multicast_v4, &addr);
+        }
+    }
+
+    if !matches!(config.ip_config, IpConfig::Ipv4Only) {
+        let index = interface.map(|it| it.index).unwrap_or(0);
+        let _ = socket.leave_multicast_v6(&config.multicast_v6, index);
+    }
+}
@@ -0,0 +1,597 @@
+use std::{collections::HashMap, net::SocketAddr, time::{Duration, Instant}};
+
+use crate::beacon::{Beacon, NodeIdentifier, Service};
+use super::interfaces::Interface;
+
+/// Period assumed for neighbours not advertizing one
+pub const DEFAULT_PERIOD: Duration = Duration::from_secs(30);
+
+/// Longest period accepted from a neighbour, longer advertized periods are clamped
+pub const MAX_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of periods without beacon before a neighbour is lost
+pub const LOST_AFTER_PERIODS: u32 = 2;
+
+/// Number of periods without beacon before a lost neighbour is forgotten
+pub const FORGET_AFTER_PERIODS: u32 = 10;
+
+/// Largest number of neighbours tracked
+/// When full, least recently seen lost neighbour is forgotten early, or new neighbours are ignored
+pub const MAX_NEIGHBOURS: usize = 1024;
+
+/// Backward jump in sequence numbers above which a neighbour is considered rebooted
+/// Smaller jumps are treated as reordered or duplicated beacons
+pub const REBOOT_BACKWARD_JUMP: u64 = 16;
+
+/// Lifecycle state of a neighbour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighbourState {
+    /// A single beacon has been received
+    New,
+
+    /// Beacons are received regularly
+    Active,
+
+    /// No beacon received for `LOST_AFTER_PERIODS` periods or goodbye received
+    Lost
+}
+
+/// A node heard on the network
+#[derive(Debug, Clone)]
+pub struct Neighbour {
+    pub node_id: NodeIdentifier,
+    pub state: NeighbourState,
+
+    /// First beacon received from this neighbour
+    pub first_seen: Instant,
+
+    /// Last fresh beacon received from this neighbour
+    pub last_seen: Instant,
+
+    /// Period advertized by this neighbour (or `DEFAULT_PERIOD`), at most `MAX_PERIOD`
+    pub period: Duration,
+
+    /// Last sequence number received
+    pub sequence_number: u64,
+
+    /// Addresses beacons were received from and when
+    pub addresses: HashMap<SocketAddr, Instant>,
+
+    /// Services advertized in last beacon
+    pub services: Vec<Service>
+}
+
+impl Neighbour {
+    fn new(node_id: NodeIdentifier, beacon: &Beacon, source: SocketAddr, now: Instant) -> Self {
+        Self {
+            node_id,
+            state: NeighbourState::New,
+            first_seen: now,
+            last_seen: now,
+            period: advertized_period(beacon),
+            sequence_number: beacon.sequence_number,
+            addresses: HashMap::from([(source, now)]),
+            services: beacon.services.clone()
+        }
+    }
+
+    /// Instant this neighbour will be lost if no beacon is received
+    /// `None` if too far away to be represented
+    pub fn lost_at(&self) -> Option<Instant> {
+        periods_after(self.last_seen, self.period, LOST_AFTER_PERIODS)
+    }
+
+    /// Instant this neighbour will be removed from table once lost
+    /// `None` if too far away to be represented
+    pub fn forget_at(&self) -> Option<Instant> {
+        periods_after(self.last_seen, self.period, FORGET_AFTER_PERIODS)
+    }
+
+    /// Classify a sequence number received from this neighbour
+    pub fn freshness(&self, sequence_number: u64, now: Instant) -> Freshness {
+        if sequence_newer(sequence_number, self.sequence_number) {
+            return Freshness::Fresh;
+        }
+
+        if sequence_number == self.sequence_number {
+            return Freshness::Stale;
+        }
+
+        let backward_jump = self.sequence_number.wrapping_sub(sequence_number);
+        let silence = now.saturating_duration_since(self.last_seen);
+
+        if sequence_number == 0 || backward_jump > REBOOT_BACKWARD_JUMP || silence > self.period {
+            Freshness::Rebooted
+        } else {
+            Freshness::Stale
+        }
+    }
+
+    /// Address of the most recent beacon
+    pub fn last_address(&self) -> Option<SocketAddr> {
+        self.addresses.iter()
+            .max_by_key(|(_, seen)| **seen)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+/// Period advertized by `beacon`, clamped to `MAX_PERIOD`
+pub fn advertized_period(beacon: &Beacon) -> Duration {
+    beacon.period.unwrap_or(DEFAULT_PERIOD).min(MAX_PERIOD)
+}
+
+/// Instant `periods` periods after `instant`, `None` on overflow
+fn periods_after(instant: Instant, period: Duration, periods: u32) -> Option<Instant> {
+    period.checked_mul(periods).and_then(|it| instant.checked_add(it))
+}
+
+/// How a received sequence number relates to the last one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    /// Sequence number is newer than the last one
+    Fresh,
+
+    /// Sequence number is a duplicate or a reordered old beacon
+    Stale,
+
+    /// Sequence number went back, neighbour restarted its counter
+    Rebooted
+}
+
+/// Is sequence number `a` newer than `b`
+/// Serial number comparison (RFC 1982) so counter wraparound is handled
+pub fn sequence_newer(a: u64, b: u64) -> bool {
+    a != b && a.wrapping_sub(b) < 1 << 63
+}
+
+/// Something that happened to a neighbour
+#[derive(Debug, Clone)]
+pub enum NeighbourEvent {
+    /// A neighbour has been heard for the first time (or again after being lost)
+    Discovered(Neighbour),
+
+    /// A new neighbour sent a second beacon
+    Active(Neighbour),
+
+    /// A neighbour advertized different services or from a new address
+    Updated(Neighbour),
+
+    /// A fresh beacon was received without any change
+    Refreshed(Neighbour),
+
+    /// A neighbour restarted its sequence numbers
+    Rebooted(Neighbour),
+
+    /// No beacon received from this neighbour for too long or goodbye received
+    Lost(Neighbour)
+}
+
+impl NeighbourEvent {
+    pub fn neighbour(&self) -> &Neighbour {
+        match self {
+            NeighbourEvent::Discovered(n) => n,
+            NeighbourEvent::Active(n) => n,
+            NeighbourEvent::Updated(n) => n,
+            NeighbourEvent::Refreshed(n) => n,
+            NeighbourEvent::Rebooted(n) => n,
+            NeighbourEvent::Lost(n) => n,
+        }
+    }
+}
+
+/// Neighbours known by this node, indexed by node ID
+#[derive(Debug, Default)]
+pub struct NeighbourTable {
+    neighbours: HashMap<NodeIdentifier, Neighbour>,
+
+    /// Number of lost neighbours forgotten early to make room for new ones
+    pub evicted: u64,
+
+    /// Number of beacons of new neighbours ignored because table was full of live ones
+    pub refused: u64
+}
+
+impl NeighbourTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node_id: &NodeIdentifier) -> Option<&Neighbour> {
+        self.neighbours.get(node_id)
+    }
+
+    /// Record a beacon received from `source`
+    /// Returns generated events, empty if beacon is not fresh
+    pub fn update(&mut self, node_id: &NodeIdentifier, beacon: &Beacon, source: SocketAddr, now: Instant)
+        -> Vec<NeighbourEvent> {
+
+        let Some(neighbour) = self.neighbours.get_mut(node_id) else {
+            if beacon.is_goodbye() || !self.make_room() {
+                return Vec::new();
+            }
+            let neighbour = Neighbour::new(node_id.clone(), beacon, source, now);
+            self.neighbours.insert(node_id.clone(), neighbour.clone());
+            return vec![NeighbourEvent::Discovered(neighbour)];
+        };
+
+        if neighbour.state == NeighbourState::Lost {
+            if beacon.is_goodbye() {
+                return Vec::new();
+            }
+            *neighbour = Neighbour::new(node_id.clone(), beacon, source, now);
+            return vec![NeighbourEvent::Discovered(neighbour.clone())];
+        }
+
+        let freshness = neighbour.freshness(beacon.sequence_number, now);
+
+        if freshness == Freshness::Stale {
+            // Same beacon heard through another path (other family or interface)
+            if neighbour.addresses.insert(source, now).is_none() {
+                return vec![NeighbourEvent::Updated(neighbour.clone())];
+            }
+            return Vec::new();
+        }
+
+        if beacon.is_goodbye() {
+            neighbour.last_seen = now;
+            neighbour.sequence_number = beacon.sequence_number;
+            neighbour.state = NeighbourState::Lost;
+            return vec![NeighbourEvent::Lost(neighbour.clone())];
+        }
+
+        let mut events = Vec::new();
+
+        // Forget paths the neighbour has not been heard through for a while
+        let period = neighbour.period;
+        neighbour.addresses.retain(|_, seen|
+            periods_after(*seen, period, LOST_AFTER_PERIODS).is_none_or(|it| it >= now));
+
+        let new_address = neighbour.addresses.insert(source, now).is_none();
+        let services_changed = neighbour.services != beacon.services;
+
+        neighbour.last_seen = now;
+        neighbour.sequence_number = beacon.sequence_number;
+        neighbour.period = advertized_period(beacon);
+        neighbour.services = beacon.services.clone();
+
+        if freshness == Freshness::Rebooted {
+            events.push(NeighbourEvent::Rebooted(neighbour.clone()));
+        }
+
+        if neighbour.state == NeighbourState::New {
+            neighbour.state = NeighbourState::Active;
+            events.push(NeighbourEvent::Active(neighbour.clone()));
+        }
+
+        if new_address || services_changed {
+            events.push(NeighbourEvent::Updated(neighbour.clone()));
+        }
+
+        if events.is_empty() {
+            events.push(NeighbourEvent::Refreshed(neighbour.clone()));
+        }
+
+        events
+    }
+
+    /// Make room for a new neighbour if table is full, by forgetting least recently seen lost neighbour
+    /// Returns false if every tracked neighbour is alive
+    fn make_room(&mut self) -> bool {
+        if self.neighbours.len() < MAX_NEIGHBOURS {
+            return true;
+        }
+
+        let oldest_lost = self.neighbours.values()
+            .filter(|it| it.state == NeighbourState::Lost)
+            .min_by_key(|it| it.last_seen)
+            .map(|it| it.node_id.clone());
+
+        match oldest_lost {
+            Some(node_id) => {
+                self.neighbours.remove(&node_id);
+                self.evicted += 1;
+                true
+            },
+            None => {
+                self.refused += 1;
+                false
+            }
+        }
+    }
+
+    /// Mark silent neighbours as lost and forget old lost neighbours
+    pub fn expire(&mut self, now: Instant) -> Vec<NeighbourEvent> {
+        let mut events = Vec::new();
+
+        self.neighbours.retain(|_, neighbour| {
+            if neighbour.state != NeighbourState::Lost && neighbour.lost_at().is_some_and(|it| it <= now) {
+                neighbour.state = NeighbourState::Lost;
+                events.push(NeighbourEvent::Lost(neighbour.clone()));
+            }
+
+            neighbour.state != NeighbourState::Lost || neighbour.forget_at().is_none_or(|it| it > now)
+        });
+
+        events
+    }
+
+    /// Forget addresses reached through an interface that went down
+    /// Neighbours without any other address are lost
+    pub fn lose_through(&mut self, interface: &Interface) -> Vec<NeighbourEvent> {
+        let mut events = Vec::new();
+
+        for neighbour in self.neighbours.values_mut() {
+            if neighbour.state == NeighbourState::Lost {
+                continue;
+            }
+
+            let before = neighbour.addresses.len();
+            neighbour.addresses.retain(|addr, _| !interface.reaches(addr));
+
+            if neighbour.addresses.is_empty() {
+                neighbour.state = NeighbourState::Lost;
+                events.push(NeighbourEvent::Lost(neighbour.clone()));
+            } else if neighbour.addresses.len() != before {
+                events.push(NeighbourEvent::Updated(neighbour.clone()));
+            }
+        }
+
+        events
+    }
+
+    /// Next instant a neighbour may be lost or forgotten
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.neighbours.values()
+            .filter_map(|it| match it.state {
+                NeighbourState::Lost => it.forget_at(),
+                _ => it.lost_at()
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::{Duration, Instant}};
+
+    use crate::beacon::Beacon;
+    use crate::testing::{beacon, NODE_ID};
+    use crate::discovery::interfaces::{Interface, InterfaceV4Addr};
+
+    use super::{sequence_newer, Freshness, NeighbourEvent, NeighbourState, NeighbourTable, MAX_NEIGHBOURS, MAX_PERIOD};
+
+    const PERIOD: Duration = Duration::from_secs(30);
+
+    fn periodic(sequence_number: u64) -> Beacon {
+        let mut beacon = beacon(sequence_number);
+        beacon.period = Some(PERIOD);
+        beacon
+    }
+
+    fn source() -> SocketAddr {
+        "192.0.2.1:3003".parse().unwrap()
+    }
+
+    #[test]
+    fn lifecycle() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        let events = table.update(&node_id, &periodic(1), source(), now);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Discovered(_)]));
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::New);
+
+        let events = table.update(&node_id, &periodic(2), source(), now + PERIOD);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Active(_)]));
+
+        let events = table.update(&node_id, &periodic(3), source(), now + PERIOD * 2);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Refreshed(_)]));
+
+        // Lost after two silent periods
+        let lost_at = now + PERIOD * 4;
+        assert_eq!(table.next_deadline(), Some(lost_at));
+        assert!(table.expire(lost_at - Duration::from_secs(1)).is_empty());
+
+        let events = table.expire(lost_at);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Lost(_)]));
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::Lost);
+
+        // Forgotten after ten silent periods
+        let forget_at = now + PERIOD * 12;
+        assert_eq!(table.next_deadline(), Some(forget_at));
+        assert!(table.expire(forget_at - Duration::from_secs(1)).is_empty());
+        assert!(table.get(&node_id).is_some());
+
+        assert!(table.expire(forget_at).is_empty());
+        assert!(table.get(&node_id).is_none());
+        assert_eq!(table.next_deadline(), None);
+    }
+
+    #[test]
+    fn lost_neighbour_discovered_again() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        table.update(&node_id, &periodic(1), source(), now);
+        table.expire(now + PERIOD * 2);
+
+        let events = table.update(&node_id, &periodic(2), source(), now + PERIOD * 3);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Discovered(_)]));
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::New);
+    }
+
+    #[test]
+    fn stale_beacon_ignored() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+
+        let mut table = NeighbourTable::new();
+        table.update(&node_id, &periodic(100), source(), now);
+
+        assert!(table.update(&node_id, &periodic(100), source(), now).is_empty());
+        assert!(table.update(&node_id, &periodic(99), source(), now).is_empty());
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        assert!(sequence_newer(1, 0));
+        assert!(!sequence_newer(0, 1));
+        assert!(!sequence_newer(5, 5));
+        assert!(sequence_newer(0, u64::MAX));
+        assert!(sequence_newer(3, u64::MAX - 3));
+        assert!(!sequence_newer(u64::MAX, 0));
+    }
+
+    #[test]
+    fn freshness_of_sequence_numbers() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+
+        let mut table = NeighbourTable::new();
+        table.update(&node_id, &periodic(100), source(), now);
+        let neighbour = table.get(&node_id).unwrap();
+
+        assert_eq!(neighbour.freshness(101, now), Freshness::Fresh);
+        assert_eq!(neighbour.freshness(100, now), Freshness::Stale);
+
+        // Reordered beacon
+        assert_eq!(neighbour.freshness(98, now), Freshness::Stale);
+
+        // Counter restarted, large backward jump or long silence
+        assert_eq!(neighbour.freshness(0, now), Freshness::Rebooted);
+        assert_eq!(neighbour.freshness(50, now), Freshness::Rebooted);
+        assert_eq!(neighbour.freshness(98, now + PERIOD * 2), Freshness::Rebooted);
+    }
+
+    #[test]
+    fn rebooted_neighbour_accepted_right_away() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+
+        let mut table = NeighbourTable::new();
+        table.update(&node_id, &periodic(100), source(), now);
+        table.update(&node_id, &periodic(101), source(), now);
+
+        let events = table.update(&node_id, &periodic(0), source(), now + PERIOD);
+
+        assert!(events.iter().any(|it| matches!(it, NeighbourEvent::Rebooted(_))));
+        assert_eq!(table.get(&node_id).unwrap().sequence_number, 0);
+    }
+
+    #[test]
+    fn goodbye_loses_neighbour() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        table.update(&node_id, &periodic(1), source(), now);
+
+        let events = table.update(&node_id, &periodic(2).goodbye(), source(), now);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Lost(_)]));
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::Lost);
+
+        // Copies of the goodbye do not bring it back
+        assert!(table.update(&node_id, &periodic(2).goodbye(), source(), now).is_empty());
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::Lost);
+    }
+
+    #[test]
+    fn goodbye_of_unknown_neighbour_ignored() {
+        let node_id = NODE_ID.to_owned();
+        let mut table = NeighbourTable::new();
+
+        assert!(table.update(&node_id, &periodic(1).goodbye(), source(), Instant::now()).is_empty());
+        assert!(table.get(&node_id).is_none());
+    }
+
+    #[test]
+    fn lose_through_interface() {
+        let node_id = NODE_ID.to_owned();
+        let other_id = "dtn://b/".to_owned();
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        let eth0 = Interface {
+            name: "eth0".into(),
+            index: 2,
+            ipv4: vec![InterfaceV4Addr { ip: "192.0.2.10".parse().unwrap(), netmask: "255.255.255.0".parse().unwrap(), broadcast: None }],
+            ipv6: Vec::new()
+        };
+
+        // Neighbour reachable through eth0 only and neighbour with another path
+        table.update(&node_id, &periodic(1), source(), now);
+        table.update(&other_id, &periodic(1), source(), now);
+        table.update(&other_id, &periodic(1), "198.51.100.1:3003".parse().unwrap(), now);
+
+        let events = table.lose_through(&eth0);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(table.get(&node_id).unwrap().state, NeighbourState::Lost);
+
+        let other = table.get(&other_id).unwrap();
+        assert_eq!(other.state, NeighbourState::New);
+        assert_eq!(other.addresses.keys().collect::<Vec<_>>(), vec![&"198.51.100.1:3003".parse::<SocketAddr>().unwrap()]);
+
+        // Lost neighbours are left alone
+        assert!(table.lose_through(&eth0).is_empty());
+    }
+
+    #[test]
+    fn copy_from_new_path_updates_neighbour() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let ipv6: SocketAddr = "[2001:db8::1]:3003".parse().unwrap();
+        let mut table = NeighbourTable::new();
+
+        table.update(&node_id, &periodic(1), source(), now);
+
+        // Same beacon heard over ipv6
+        let events = table.update(&node_id, &periodic(1), ipv6, now);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Updated(_)]));
+        assert!(table.get(&node_id).unwrap().addresses.contains_key(&ipv6));
+
+        assert!(table.update(&node_id, &periodic(1), ipv6, now).is_empty());
+    }
+
+    #[test]
+    fn clamps_huge_period() {
+        let node_id = NODE_ID.to_owned();
+        let now = Instant::now();
+        let mut huge = beacon(1);
+        huge.period = Some(Duration::from_secs(u64::MAX));
+
+        let mut table = NeighbourTable::new();
+        table.update(&node_id, &huge, source(), now);
+        table.update(&node_id, &huge.next(), source(), now);
+
+        assert_eq!(table.get(&node_id).unwrap().period, MAX_PERIOD);
+        assert!(table.next_deadline().is_some());
+        assert!(table.expire(now).is_empty());
+    }
+
+    #[test]
+    fn full_table_forgets_lost_neighbours_first() {
+        let now = Instant::now();
+        let mut table = NeighbourTable::new();
+
+        for i in 0..MAX_NEIGHBOURS {
+            table.update(&format!("dtn://{}/", i), &periodic(1), source(), now + Duration::from_secs(i as u64));
+        }
+
+        // Only live neighbours, new ones are ignored
+        assert!(table.update(&"dtn://new/".to_owned(), &periodic(1), source(), now).is_empty());
+        assert_eq!(table.refused, 1);
+
+        // Least recently seen lost neighbour makes room
+        table.update(&"dtn://2/".to_owned(), &periodic(2).goodbye(), source(), now + PERIOD * 2);
+        table.update(&"dtn://1/".to_owned(), &periodic(2).goodbye(), source(), now + PERIOD);
+
+        let events = table.update(&"dtn://new/".to_owned(), &periodic(1), source(), now);
+        assert!(matches!(events.as_slice(), [NeighbourEvent::Discovered(_)]));
+        assert_eq!(table.evicted, 1);
+        assert!(table.get(&"dtn://1/".to_owned()).is_none());
+        assert!(table.get(&"dtn://2/".to_owned()).is_some());
+    }
+}
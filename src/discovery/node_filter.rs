@@ -0,0 +1,160 @@
+use std::{fmt::Display, io, path::Path, str::FromStr};
+
+use crate::util::parse_lines;
+
+/// A pattern matching `dtn://` or `ipn:` node ids
+/// `*` matches any sequence of characters (`dtn://*.archipel.net/`, `ipn:100.*`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EidPattern(String);
+
+impl EidPattern {
+    pub fn matches(&self, node_id: &str) -> bool {
+        glob_matches(self.0.as_bytes(), node_id.as_bytes())
+    }
+}
+
+impl FromStr for EidPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.starts_with("dtn://") || s.starts_with("ipn:") {
+            Ok(EidPattern(s.to_owned()))
+        } else {
+            Err(format!("Invalid node id pattern {} (expected dtn://... or ipn:...)", s))
+        }
+    }
+}
+
+impl Display for EidPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Node ids contacts are configured for
+#[derive(Debug, Clone, Default)]
+pub struct NodeFilter {
+    /// Only accept these node ids, every node id if empty
+    pub allow: Vec<EidPattern>,
+
+    /// Never accept these node ids
+    pub deny: Vec<EidPattern>
+}
+
+impl NodeFilter {
+    pub fn accepts(&self, node_id: &str) -> bool {
+        if self.deny.iter().any(|it| it.matches(node_id)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|it| it.matches(node_id))
+    }
+
+    /// Add patterns from a file, one `allow PATTERN` or `deny PATTERN` per line
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let rules = parse_lines(path, "node filter rule", |line| {
+            let (list, pattern) = line.split_once(char::is_whitespace)?;
+            let pattern = EidPattern::from_str(pattern).ok()?;
+
+            match list {
+                "allow" => Some((true, pattern)),
+                "deny" => Some((false, pattern)),
+                _ => None
+            }
+        })?;
+
+        for (allow, pattern) in rules {
+            if allow {
+                self.allow.push(pattern);
+            } else {
+                self.deny.push(pattern);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Match `name` against `pattern` where `*` matches any sequence
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+
+    // Position of last `*` in pattern and of name when it was reached
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let last `*` match one more character
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|it| *it == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{glob_matches, EidPattern, NodeFilter};
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        glob_matches(pattern.as_bytes(), name.as_bytes())
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<EidPattern> {
+        patterns.iter().map(|it| EidPattern::from_str(it).unwrap()).collect()
+    }
+
+    #[test]
+    fn glob() {
+        assert!(matches("dtn://a/", "dtn://a/"));
+        assert!(!matches("dtn://a/", "dtn://b/"));
+        assert!(matches("dtn://*.archipel.net/", "dtn://a.archipel.net/"));
+        assert!(!matches("dtn://*.archipel.net/", "dtn://a.archipel.org/"));
+        assert!(matches("ipn:100.*", "ipn:100.1"));
+        assert!(!matches("ipn:100.*", "ipn:1000.1"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXbYbc"));
+        assert!(!matches("a*b*c", "aXbYbcd"));
+        assert!(matches("a**", "a"));
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        assert!(EidPattern::from_str("http://a/").is_err());
+        assert!(EidPattern::from_str(" ipn:1.* ").is_ok());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = NodeFilter {
+            allow: patterns(&["dtn://*.archipel.net/"]),
+            deny: patterns(&["dtn://bad.archipel.net/"])
+        };
+
+        assert!(filter.accepts("dtn://good.archipel.net/"));
+        assert!(!filter.accepts("dtn://bad.archipel.net/"));
+        assert!(!filter.accepts("dtn://other/"));
+    }
+
+    #[test]
+    fn empty_allow_list_accepts_every_node() {
+        let filter = NodeFilter { allow: Vec::new(), deny: patterns(&["ipn:1.*"]) };
+
+        assert!(filter.accepts("ipn:2.0"));
+        assert!(!filter.accepts("ipn:1.0"));
+    }
+}
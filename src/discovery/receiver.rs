@@ -1,152 +1,564 @@
-use std::{collections::{HashMap, HashSet}, io::ErrorKind, mem::{discriminant, Discriminant}, net::{IpAddr, SocketAddr, UdpSocket}, sync::{atomic::AtomicBool, Arc}, thread, time::Duration};
-use std::sync::atomic::Ordering;
+use std::{collections::HashMap, io::ErrorKind, net::{IpAddr, SocketAddr, UdpSocket}, sync::{mpsc, Arc}, time::{Duration, Instant, SystemTime}};
 
+use rand::Rng;
 use ud3tn_aap::{config::{ConfigBundle, Contact, ContactDataRate}, AapStream, RegisteredAgent};
 
-use crate::{beacon::{Beacon, NodeIdentifier, Service}, IpConfig};
+use crate::beacon::{Beacon, BeaconError, NodeIdentifier};
+use super::{announcer, interfaces::Interface, limits::Limiter, replay::ReplayGuard, trust::TrustStore, schedule::ScheduleReset, stop::StopSignal, watcher::InterfaceChange, cla::{self, ClaKind}, contacts::{ContactAction, ContactTable, CONTACT_LIFETIME_PERIODS}, neighbours::{Neighbour, NeighbourEvent, NeighbourTable}, DiscoveryConfig, IpConfig, SharedBeacon};
 
-type AvailableClaSet = HashSet<Discriminant<Service>>;
+/// Shortest time receiver blocks waiting for a datagram
+const MIN_WAIT: Duration = Duration::from_millis(1);
 
+/// Longest time receiver blocks waiting for a datagram
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// Shortest time between two unicast replies to the same peer
+const REPLY_MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest random delay before answering a solicitation
+const SOLICITATION_REPLY_DELAY: Duration = Duration::from_secs(1);
+
+#[allow(clippy::too_many_arguments)]
 pub fn receiver_task(
-    verbose: bool,
-    ip_config: IpConfig,
-    continue_trigger: Arc<AtomicBool>,
+    config: DiscoveryConfig,
+    stop_signal: Arc<StopSignal>,
     socket: UdpSocket,
     self_node_id: NodeIdentifier,
-    mut aap: RegisteredAgent<impl AapStream>,
-    emitted_beacon: Beacon
+    aap: Option<RegisteredAgent<impl AapStream>>,
+    emitted_beacon: SharedBeacon,
+    interface_changes: mpsc::Receiver<InterfaceChange>,
+    reset: Arc<ScheduleReset>
 ) {
-    socket.set_nonblocking(true)
-        .expect("Receiver socket can't be set non-blocking");
+    let available_cla: Vec<ClaKind> = emitted_beacon.read().unwrap().services.iter()
+        .filter_map(ClaKind::of)
+        .collect();
+
+    let config_limits = config.limits.clone();
+    let trust_store = config.trust_store.clone();
+    let replay_guard = config.replay_window.map(ReplayGuard::new);
+
+    let mut receiver = Receiver {
+        config,
+        self_node_id,
+        aap,
+        available_cla,
+        neighbours: NeighbourTable::new(),
+        contacts: ContactTable::new(),
+        invalid_beacons: HashMap::new(),
+        reset,
+        socket,
+        emitted_beacon,
+        replies: HashMap::new(),
+        pending_replies: Vec::new(),
+        limiter: Limiter::new(&config_limits),
+        trust_store,
+        replay_guard,
+        filtered_beacons: 0
+    };
 
-    let available_cla: AvailableClaSet = HashSet::from_iter(
-        emitted_beacon.services.into_iter()
-            .filter(Service::is_cla)
-            .map(|it| discriminant(&it))
-        );
+    let mut buf = vec![0_u8; receiver.limiter.buffer_size()];
 
-    let mut buf = [0_u8; 100_000];
-    let mut seq_nums:HashMap<SocketAddr, u64> = HashMap::new();
+    while !stop_signal.is_stopped() {
 
-    while continue_trigger.load(Ordering::SeqCst) {
+        // Block until a datagram arrives, next neighbour deadline or next pending reply
+        // Pending datagrams are drained one per iteration without waiting
+        let timeout = receiver.next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or(MAX_WAIT)
+            .clamp(MIN_WAIT, MAX_WAIT);
 
-        match socket.recv_from(&mut buf) {
+        receiver.socket.set_read_timeout(Some(timeout))
+            .expect("Receiver socket timeout can't be set");
+
+        match receiver.socket.recv_from(&mut buf) {
             Ok((bytes_red, source)) => {
-                if bytes_red > 0 {
-                    try_beacon(
-                        verbose, 
-                        &ip_config,
-                        &buf[0..bytes_red], 
-                        source,
-                        &mut seq_nums,
-                        &self_node_id,
-                        &mut aap,
-                        &available_cla
-                    )
+                // Empty datagrams are sent by stopper to wake receiver up
+                if bytes_red > 0 && receiver.accept_datagram(bytes_red, source) {
+                    receiver.try_beacon(&buf[0..bytes_red], source)
                 }
             },
             Err(e) => {
                 match e.kind() {
-                    ErrorKind::WouldBlock => {}
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => {}
                     _ => println!("Errer receiving packet from socket {}", e)
                 }
             },
         }
 
-        thread::sleep(Duration::from_millis(300));
+        for change in interface_changes.try_iter() {
+            match change {
+                InterfaceChange::Up(interface) => if receiver.config.verbose {
+                    println!("Receiving beacons on {}", interface.name)
+                },
+                InterfaceChange::Down(interface) => receiver.lose_through(&interface),
+            }
+        }
+
+        receiver.expire_neighbours();
+        receiver.send_pending_replies();
+    }
+
+    receiver.report_invalid_beacons();
+    receiver.report_dropped();
+
+    if receiver.aap.is_some() {
+        receiver.report_config_bundles();
     }
 }
 
-fn try_beacon(
-    verbose: bool,
-    ip_config: &IpConfig,
-    buf: &[u8],
-    source: SocketAddr,
-    seq_num_index: &mut HashMap<SocketAddr, u64>,
-    self_node_id: &NodeIdentifier,
-    aap: &mut RegisteredAgent<impl AapStream>,
-    available_cla: &AvailableClaSet
-){
-    match Beacon::parse(buf) {
-        Ok(beacon) => {
-
-            if let Some(node_id) = &beacon.node_id {
-                if *node_id == *self_node_id {
-                    if verbose {
-                        println!("Received beacon from current node id, ignoring");
-                    }
-                    return;
+/// State of the receiving side of discovery
+struct Receiver<S: AapStream> {
+    config: DiscoveryConfig,
+    self_node_id: NodeIdentifier,
+
+    /// Core config agent, contacts are not configured if `None` (announce-only)
+    aap: Option<RegisteredAgent<S>>,
+
+    /// Convergence layers supported locally
+    available_cla: Vec<ClaKind>,
+
+    /// Neighbours heard on the network
+    neighbours: NeighbourTable,
+
+    /// Contacts configured in core
+    contacts: ContactTable,
+
+    /// Number of invalid beacons received by cause
+    invalid_beacons: HashMap<&'static str, u64>,
+
+    /// Raised when the neighbourhood changes
+    reset: Arc<ScheduleReset>,
+
+    /// Socket replies are sent through
+    socket: UdpSocket,
+
+    /// Last beacon emitted by announcer
+    emitted_beacon: SharedBeacon,
+
+    /// Last unicast reply by peer address
+    replies: HashMap<SocketAddr, Instant>,
+
+    /// Replies to solicitations waiting for their delay, with time they are due
+    pending_replies: Vec<(Instant, SocketAddr)>,
+
+    /// Rate limits on received packets and sent config bundles
+    limiter: Limiter,
+
+    /// Keys trusted to sign beacons of each node, updated on first use
+    trust_store: Option<TrustStore>,
+
+    /// Last authenticated beacon of each node
+    replay_guard: Option<ReplayGuard>,
+
+    /// Number of beacons ignored because of node filter
+    filtered_beacons: u64
+}
+
+impl<S: AapStream> Receiver<S> {
+
+    fn parse(&mut self, buf: &[u8], source: SocketAddr) -> Option<Beacon> {
+        // Closed discovery domain, only beacons sealed with a group key are accepted
+        let opened = self.config.group_keys.as_ref().map(|keys| keys.open(buf));
+
+        let mut result = match opened {
+            Some(Ok(buf)) if self.config.strict => Beacon::parse_strict(&buf),
+            Some(Ok(buf)) => Beacon::parse(&buf),
+            Some(Err(e)) => Err(e),
+            None if self.config.strict => Beacon::parse_strict(buf),
+            None => Beacon::parse(buf),
+        };
+
+        // Unauthenticated beacons are dropped before any contact is configured
+        if let Some(keys) = &self.config.shared_keys {
+            result = result.and_then(|beacon| keys.verify(beacon));
+        }
+
+        // Filtered nodes are neither tracked nor answered, their keys are not pinned
+        // and their beacons are not recorded by replay guard
+        if let Ok(Beacon { node_id: Some(node_id), .. }) = &result {
+            if !self.config.node_filter.accepts(node_id) {
+                self.filtered_beacons += 1;
+                if self.config.verbose {
+                    println!("Received beacon from filtered node {} at {}, ignoring", node_id, source);
                 }
+                return None;
             }
+        }
 
-            let is_fresh = match seq_num_index.get(&source) {
-                Some(seq_num) => beacon.sequence_number > *seq_num, //bug Should tke into account sequence number overflowing (reset to 0)
-                None => {
-                    println!("New neighbour discovered at {} (node id:{})", source,
-                        beacon.node_id.as_ref().unwrap_or(&"<undefined>".to_owned()));
-                    true
-                },
-            };
+        if let Some(trust_store) = &mut self.trust_store {
+            result = result.and_then(|beacon| trust_store.verify(beacon));
+        }
+
+        // Copies of the last beacon only refresh paths the neighbour is already known through
+        if let Some(replay_guard) = &mut self.replay_guard {
+            let neighbours = &self.neighbours;
+            result = result.and_then(|beacon| {
+                let copy = replay_guard.check(&beacon, SystemTime::now())?;
+                let known_path = beacon.node_id.as_ref()
+                    .and_then(|node_id| neighbours.get(node_id))
+                    .is_some_and(|neighbour| neighbour.addresses.contains_key(&source));
+
+                if copy && !known_path { Err(BeaconError::Replayed) } else { Ok(beacon) }
+            });
+        }
 
-            if !is_fresh {
-                return;
+        match result {
+            Ok(beacon) => Some(beacon),
+            Err(e) => {
+                self.count_invalid(&e);
+                if self.config.verbose {
+                    println!("Invalid beacon received from {} ({}) : {}", source, e.cause(), e);
+                }
+                None
             }
+        }
+    }
+
+    fn count_invalid(&mut self, error: &BeaconError) {
+        *self.invalid_beacons.entry(error.cause()).or_default() += 1;
+    }
+
+    /// Earliest of next neighbour deadline and next pending reply
+    fn next_deadline(&self) -> Option<Instant> {
+        let reply = self.pending_replies.iter().map(|(due, _)| *due).min();
+
+        match (self.neighbours.next_deadline(), reply) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        }
+    }
+
+    fn send_pending_replies(&mut self) {
+        let now = Instant::now();
+
+        let (due, pending) = std::mem::take(&mut self.pending_replies).into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.pending_replies = pending;
+
+        for (_, peer) in due {
+            self.reply(peer);
+        }
+    }
+
+    fn expire_neighbours(&mut self) {
+        for event in self.neighbours.expire(Instant::now()) {
+            self.handle_event(&event);
+        }
+    }
+
+    fn lose_through(&mut self, interface: &Interface) {
+        for event in self.neighbours.lose_through(interface) {
+            self.handle_event(&event);
+        }
+    }
+
+    /// Log an event and forward it to library user
+    fn handle_event(&mut self, event: &NeighbourEvent) {
+        let neighbour = event.neighbour();
+
+        if matches!(event, NeighbourEvent::Discovered(_) | NeighbourEvent::Rebooted(_) | NeighbourEvent::Lost(_)) {
+            self.reset.raise();
+        }
+
+        match event {
+            NeighbourEvent::Discovered(_) => println!("New neighbour discovered at {} (node id:{})",
+                format_address(neighbour), neighbour.node_id),
+            NeighbourEvent::Active(_) => if self.config.verbose {
+                println!("Neighbour {} is active", neighbour.node_id)
+            },
+            NeighbourEvent::Updated(_) => if self.config.verbose {
+                println!("Neighbour {} updated at {}", neighbour.node_id, format_address(neighbour))
+            },
+            NeighbourEvent::Refreshed(_) => {},
+            NeighbourEvent::Rebooted(_) => println!("Neighbour {} rebooted (sequence number reset to {})",
+                neighbour.node_id, neighbour.sequence_number),
+            NeighbourEvent::Lost(_) => {
+                println!("Neighbour {} lost", neighbour.node_id);
+                self.remove_contact(&neighbour.node_id);
+            },
+        }
+
+        if let Some(events) = &self.config.events {
+            if events.send(event.clone()).is_err() && self.config.verbose {
+                println!("Neighbour event receiver is gone");
+            }
+        }
+    }
+
+    /// Remove contact configured for a neighbour from core
+    fn remove_contact(&mut self, node_id: &NodeIdentifier) {
+        if !self.contacts.remove(node_id) {
+            return;
+        }
+
+        // Contact expires by itself in core if removal is dropped
+        if !self.limiter.accept_config_bundle(Instant::now()) {
+            println!("Too many config bundles, contact to {} not removed", node_id);
+            return;
+        }
+
+        self.contacts.bundles_sent += 1;
+
+        if self.config.verbose {
+            println!("Removing contact to {}", node_id);
+        }
+
+        if let Some(aap) = &mut self.aap {
+            if let Err(e) = aap.send_config(ConfigBundle::DeleteContact(node_id.clone())) {
+                println!("Error removing contact from ud3tn config {}", e);
+            }
+        }
+    }
+
+    fn accept_datagram(&mut self, size: usize, source: SocketAddr) -> bool {
+        let accepted = self.limiter.accept_datagram(size, source.ip(), Instant::now());
 
-            if matches!(ip_config, IpConfig::Ipv6Only) {
-                if let IpAddr::V6(ipv6) = source.ip() {
-                    if ipv6.to_ipv4().is_some() {
-                        if verbose {
-                            println!("Received beacon from ipv4, ignoring");
-                        }
-                        return;
+        if !accepted && self.config.verbose {
+            println!("Dropped datagram of {} bytes from {}", size, source);
+        }
+
+        accepted
+    }
+
+    fn report_dropped(&self) {
+        for (cause, count) in &self.limiter.dropped {
+            println!("{} packets dropped ({})", count, cause);
+        }
+
+        if self.filtered_beacons > 0 {
+            println!("{} beacons from filtered nodes ignored", self.filtered_beacons);
+        }
+
+        if self.neighbours.evicted > 0 || self.neighbours.refused > 0 {
+            println!("Neighbour table full, {} lost neighbours forgotten early, {} beacons of new neighbours ignored",
+                self.neighbours.evicted, self.neighbours.refused);
+        }
+    }
+
+    fn report_invalid_beacons(&self) {
+        for (cause, count) in &self.invalid_beacons {
+            println!("{} invalid beacons received ({})", count, cause);
+        }
+    }
+
+    fn report_config_bundles(&self) {
+        println!("{} config bundles sent to core, {} saved", self.contacts.bundles_sent, self.contacts.bundles_saved);
+    }
+
+    fn try_beacon(&mut self, buf: &[u8], source: SocketAddr) {
+        let verbose = self.config.verbose;
+
+        let Some(beacon) = self.parse(buf, source) else {
+            return;
+        };
+
+        if beacon.node_id.as_ref() == Some(&self.self_node_id) {
+            if verbose {
+                println!("Received beacon from current node id, ignoring");
+            }
+            return;
+        }
+
+        if matches!(self.config.ip_config, IpConfig::Ipv6Only) {
+            if let IpAddr::V6(ipv6) = source.ip() {
+                if ipv6.to_ipv4().is_some() {
+                    if verbose {
+                        println!("Received beacon from ipv4, ignoring");
                     }
+                    return;
                 }
             }
+        }
 
-            seq_num_index.insert(source, beacon.sequence_number);
+        // Solicitations are answered after a random delay so neighbours do not all answer at once
+        if beacon.solicitation {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..SOLICITATION_REPLY_DELAY);
+            if verbose {
+                println!("Solicitation received from {}, replying in {}ms", source, delay.as_millis());
+            }
+            self.pending_replies.push((Instant::now() + delay, source));
+        }
 
+        let Some(node_id) = beacon.node_id.clone() else {
+            if !beacon.solicitation {
+                eprintln!("Received beacon without eid, ignoring");
+            }
+            return;
+        };
+
+        if !self.limiter.accept_node(&node_id, Instant::now()) {
             if verbose {
-                println!("Received beacon #{} from {}", beacon.sequence_number, source);
-                println!("{:?}", beacon)
+                println!("Dropped beacon from {} at {}, too many beacons", node_id, source);
             }
+            return;
+        }
 
-            let Some(service) = beacon.services.iter().find(|it| available_cla.contains(&discriminant(it))) else {
-                eprintln!("No compatible CLA found in beacon, ignoring");
-                return;
-            };
+        let events = self.neighbours.update(&node_id, &beacon, source, Instant::now());
 
-            let Some(node_id) = beacon.node_id else {
-                eprintln!("Received beacon without eid, ignoring");
-                return;
-            };
+        if events.is_empty() {
+            return;
+        }
+
+        for event in &events {
+            self.handle_event(event);
+        }
+
+        if events.iter().any(|it| matches!(it, NeighbourEvent::Lost(_))) {
+            return;
+        }
+
+        // New neighbour may wait a whole period before hearing from us
+        if !beacon.solicitation
+            && events.iter().any(|it| matches!(it, NeighbourEvent::Discovered(_) | NeighbourEvent::Rebooted(_))) {
+            self.reply(source);
+        }
+
+        if verbose {
+            println!("Received beacon #{} from {}", beacon.sequence_number, source);
+            println!("{:?}", beacon)
+        }
+
+        // Announce-only, neighbours are tracked but contacts are not configured
+        if self.aap.is_none() {
+            return;
+        }
+
+        let candidates = self.config.cla_policy.candidates(&self.available_cla, &beacon.services);
 
-            let duration = beacon.period.map(|it| it*2).unwrap_or(Duration::from_secs(30));
+        if candidates.is_empty() {
+            eprintln!("No compatible CLA found in beacon, ignoring");
+            return;
+        }
+
+        let Some(neighbour) = self.neighbours.get(&node_id) else {
+            return;
+        };
+
+        let address = self.config.address_policy.select(neighbour).unwrap_or(source);
+
+        let clas: Vec<String> = candidates.iter()
+            .map(|service| service.as_cla_address(address).unwrap())
+            .collect();
+
+        // With failover, CLA configured after a probe is kept while neighbour still advertises it
+        let failover = self.config.cla_policy.failover;
+        let mut cla = self.contacts.cla_address(&node_id)
+            .filter(|configured| failover && clas.iter().any(|it| it == configured))
+            .unwrap_or(&clas[0])
+            .to_owned();
+
+        let changed = events.iter().any(|it| matches!(it,
+            NeighbourEvent::Discovered(_) | NeighbourEvent::Updated(_) | NeighbourEvent::Rebooted(_)));
 
-            let cla = service.as_cla_address(source.ip()).unwrap();
+        // Clamped to `MAX_PERIOD` by neighbour table
+        let period = neighbour.period;
+        let now = Instant::now();
 
+        let action = self.contacts.plan(&node_id, &cla, period, changed, now);
+
+        if action == ContactAction::Keep {
             if verbose {
-                println!("Adding contact to {} with cla {} during {}s", &node_id, &cla, duration.as_secs());
+                println!("Contact to {} with cla {} still valid, config bundle saved ({} so far)",
+                    &node_id, &cla, self.contacts.bundles_saved);
             }
+            return;
+        }
+
+        let duration = period * CONTACT_LIFETIME_PERIODS;
 
-            let config_bundle = ConfigBundle::AddContact {
-                eid: node_id,
+        // Contact is not recorded as configured, it is configured again on next beacon
+        if !self.limiter.accept_config_bundle(Instant::now()) {
+            if verbose {
+                println!("Too many config bundles, contact to {} delayed", &node_id);
+            }
+            return;
+        }
+
+        // First CLA accepting connections, preferred one if none does
+        if failover {
+            if let Some(reachable) = candidates.iter().position(|service| cla::probe(service, address)) {
+                cla = clas[reachable].clone();
+            }
+        }
+
+        if verbose {
+            println!("Configuring contact to {} with cla {} during {}s", &node_id, &cla, duration.as_secs());
+        }
+
+        if let Err(e) = self.send_contact(&node_id, &cla, duration, action == ContactAction::Replace) {
+            println!("Error adding comtact ton ud3tn config {}", e);
+            return;
+        }
+
+        self.contacts.configured(node_id, cla, now + duration);
+    }
+
+    /// Send last emitted beacon to `peer` in unicast, at most once every `REPLY_MIN_INTERVAL`
+    /// Nothing is sent in passive mode
+    fn reply(&mut self, peer: SocketAddr) {
+        if self.config.passive {
+            return;
+        }
+
+        let now = Instant::now();
+
+        self.replies.retain(|_, sent| now.duration_since(*sent) < REPLY_MIN_INTERVAL);
+
+        if self.replies.contains_key(&peer) {
+            return;
+        }
+
+        let beacon = self.emitted_beacon.read().unwrap().clone();
+        let buf = announcer::encode(&self.config, &beacon);
+
+        match self.socket.send_to(&buf, peer) {
+            Ok(_) => if self.config.verbose { println!("Replied beacon #{} to {}", beacon.sequence_number, peer) },
+            Err(e) => println!("Error replying beacon to {} : {}", peer, e),
+        }
+
+        self.replies.insert(peer, now);
+    }
+
+    /// Send a contact to core, replacing contacts of this node if `replace` is set
+    fn send_contact(&mut self, node_id: &NodeIdentifier, cla: &str, duration: Duration, replace: bool)
+        -> Result<(), ud3tn_aap::Error> {
+        let eid = node_id.clone();
+        let cla_address = cla.to_owned();
+        let contacts = vec![
+            Contact::from_now_during(
+                duration,
+                ContactDataRate::Unlimited)
+        ];
+
+        let config_bundle = if replace {
+            ConfigBundle::ReplaceContact {
+                eid,
                 reliability: None,
-                cla_address: cla,
+                cla_address,
                 reaches_eid: Vec::new(),
-                contacts: vec![
-                    Contact::from_now_during(
-                        duration,
-                        ContactDataRate::Unlimited)
-                ],
-            };
+                contacts,
+            }
+        } else {
+            ConfigBundle::AddContact {
+                eid,
+                reliability: None,
+                cla_address,
+                reaches_eid: Vec::new(),
+                contacts,
+            }
+        };
 
-            let result = aap.send_config(config_bundle);
+        if let Some(aap) = &mut self.aap {
+            aap.send_config(config_bundle)?;
+            self.contacts.bundles_sent += 1;
+        }
 
-            if let Err(e) = result {
-                println!("Error adding comtact ton ud3tn config {}", e);
-            }
-                    
-        },
-        Err(e) => if verbose { println!("Invalid beacon received {}", e) },
-    };
-}
\ No newline at end of file
+        Ok(())
+    }
+}
+
+fn format_address(neighbour: &Neighbour) -> String {
+    neighbour.last_address()
+        .map(|it| it.to_string())
+        .unwrap_or("<unknown>".to_owned())
+}
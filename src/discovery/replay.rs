@@ -0,0 +1,136 @@
+use std::{collections::HashMap, time::{Duration, Instant, SystemTime}};
+
+use crate::beacon::{Beacon, BeaconError, NodeIdentifier};
+
+use super::neighbours::sequence_newer;
+
+/// Copies of the same beacon are accepted this long after the first one,
+/// long enough for copies heard through other families or interfaces
+const DUPLICATE_DELAY: Duration = Duration::from_secs(2);
+
+/// Last beacon accepted from a node
+#[derive(Debug, Clone, Copy)]
+struct LastBeacon {
+    timestamp: SystemTime,
+    sequence_number: u64,
+    received: Instant
+}
+
+/// Rejects beacons outside a freshness window and beacons older than the last one
+/// accepted from the same node id, whatever address they come from
+/// Beacons must be authenticated before, or timestamp and sequence number can be forged
+#[derive(Debug)]
+pub struct ReplayGuard {
+    window: Duration,
+
+    /// Last beacon accepted by node
+    last: HashMap<NodeIdentifier, LastBeacon>
+}
+
+impl ReplayGuard {
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: HashMap::new() }
+    }
+
+    /// Returns whether `beacon` is a copy of the last one accepted from its node
+    /// Copies can be replayed by anyone, they must not teach a new address of the node
+    pub fn check(&mut self, beacon: &Beacon, now: SystemTime) -> Result<bool, BeaconError> {
+        let Some(timestamp) = beacon.timestamp else {
+            return Err(BeaconError::NotFresh);
+        };
+
+        let skew = now.duration_since(timestamp)
+            .or_else(|_| timestamp.duration_since(now))
+            .unwrap_or_default();
+
+        if skew > self.window {
+            return Err(BeaconError::NotFresh);
+        }
+
+        let Some(node_id) = &beacon.node_id else {
+            return Ok(false);
+        };
+
+        let sequence_number = beacon.sequence_number;
+        let received = Instant::now();
+
+        // A sequence number going back is only a restart if timestamp moved forward
+        if let Some(last) = self.last.get(node_id) {
+            let older = timestamp < last.timestamp
+                || (timestamp == last.timestamp && sequence_newer(last.sequence_number, sequence_number));
+
+            let duplicate = timestamp == last.timestamp && sequence_number == last.sequence_number;
+
+            if older || (duplicate && received.duration_since(last.received) > DUPLICATE_DELAY) {
+                return Err(BeaconError::Replayed);
+            }
+
+            if duplicate {
+                return Ok(true);
+            }
+        }
+
+        self.last.insert(node_id.clone(), LastBeacon { timestamp, sequence_number, received });
+
+        // Beacons older than the window are rejected anyway
+        let window = self.window;
+        self.last.retain(|_, last|
+            !now.duration_since(last.timestamp).is_ok_and(|age| age > window));
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::beacon::{Beacon, BeaconError};
+    use crate::testing::beacon;
+
+    use super::ReplayGuard;
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    fn stamped(sequence_number: u64, timestamp: SystemTime) -> Beacon {
+        let mut beacon = beacon(sequence_number);
+        beacon.timestamp = Some(timestamp);
+        beacon
+    }
+
+    #[test]
+    fn rejects_beacon_outside_window() {
+        let now = SystemTime::now();
+        let mut guard = ReplayGuard::new(WINDOW);
+
+        assert!(matches!(guard.check(&Beacon::new(), now), Err(BeaconError::NotFresh)));
+        assert!(matches!(guard.check(&stamped(1, now - WINDOW * 2), now), Err(BeaconError::NotFresh)));
+        assert!(matches!(guard.check(&stamped(1, now + WINDOW * 2), now), Err(BeaconError::NotFresh)));
+        assert!(guard.check(&stamped(1, now - WINDOW / 2), now).is_ok());
+    }
+
+    #[test]
+    fn rejects_older_beacon() {
+        let now = SystemTime::now();
+        let mut guard = ReplayGuard::new(WINDOW);
+
+        assert!(guard.check(&stamped(5, now), now).is_ok());
+        assert!(guard.check(&stamped(6, now), now).is_ok());
+        assert!(matches!(guard.check(&stamped(5, now), now), Err(BeaconError::Replayed)));
+        assert!(matches!(guard.check(&stamped(7, now - Duration::from_secs(1)), now), Err(BeaconError::Replayed)));
+    }
+
+    #[test]
+    fn accepts_copies_and_restarts() {
+        let now = SystemTime::now();
+        let mut guard = ReplayGuard::new(WINDOW);
+
+        assert!(!guard.check(&stamped(5, now), now).unwrap());
+
+        // Same beacon heard through another interface
+        assert!(guard.check(&stamped(5, now), now).unwrap());
+
+        // Sequence number restarted with a later timestamp
+        assert!(!guard.check(&stamped(0, now + Duration::from_secs(1)), now).unwrap());
+    }
+}
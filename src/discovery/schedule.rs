@@ -0,0 +1,160 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use clap::ValueEnum;
+use rand::Rng;
+
+use super::neighbours::MAX_PERIOD;
+
+/// Shortest interval between two beacons in trickle mode
+/// Beacon period is advertized in whole seconds, so is every interval
+pub const TRICKLE_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default random variation of intervals, as a fraction of the interval
+pub const DEFAULT_JITTER: f64 = 0.1;
+
+/// How intervals between two beacons are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum EmissionMode {
+    /// Beacons are emitted every period
+    #[default]
+    Fixed,
+
+    /// Trickle algorithm (RFC 6206)
+    /// Interval starts short and doubles up to period, it is reset when the neighbourhood changes
+    Trickle
+}
+
+/// Intervals between beacons of an announcer
+#[derive(Debug)]
+pub struct Schedule {
+    mode: EmissionMode,
+    period: Duration,
+    jitter: f64,
+
+    /// Current interval before jitter
+    interval: Duration
+}
+
+impl Schedule {
+    /// Period is clamped between `TRICKLE_MIN_INTERVAL` and `MAX_PERIOD`, neighbours would clamp a longer one anyway
+    pub fn new(mode: EmissionMode, period: Duration, jitter: f64) -> Self {
+        let period = period.clamp(TRICKLE_MIN_INTERVAL, MAX_PERIOD);
+
+        let interval = match mode {
+            EmissionMode::Fixed => period,
+            EmissionMode::Trickle => TRICKLE_MIN_INTERVAL,
+        };
+
+        Self { mode, period, jitter: jitter.clamp(0.0, 1.0), interval }
+    }
+
+    /// Interval until next beacon, advertized as period of the beacon sent now
+    pub fn next_interval(&mut self) -> Duration {
+        let secs = self.interval.as_secs();
+        let spread = (secs as f64 * self.jitter).round() as u64;
+
+        let jittered = rand::thread_rng().gen_range(secs - spread.min(secs)..=secs + spread);
+
+        if self.mode == EmissionMode::Trickle {
+            self.interval = (self.interval * 2).min(self.period);
+        }
+
+        Duration::from_secs(jittered.max(TRICKLE_MIN_INTERVAL.as_secs()))
+    }
+
+    /// Neighbourhood changed, go back to shortest interval in trickle mode
+    /// Returns true if current interval should end now
+    pub fn reset(&mut self) -> bool {
+        if self.mode != EmissionMode::Trickle || self.interval == TRICKLE_MIN_INTERVAL {
+            return false;
+        }
+
+        self.interval = TRICKLE_MIN_INTERVAL;
+        true
+    }
+}
+
+/// Raised by receiver and interface watcher when the neighbourhood changes
+#[derive(Debug, Default)]
+pub struct ScheduleReset(AtomicBool);
+
+impl ScheduleReset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn raise(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if raised since last call
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::discovery::neighbours::MAX_PERIOD;
+
+    use super::{EmissionMode, Schedule, TRICKLE_MIN_INTERVAL};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn fixed_intervals_stay_within_jitter() {
+        let mut schedule = Schedule::new(EmissionMode::Fixed, secs(100), 0.1);
+
+        for _ in 0..100 {
+            let interval = schedule.next_interval();
+            assert!(interval >= secs(90) && interval <= secs(110), "{:?}", interval);
+        }
+
+        let mut exact = Schedule::new(EmissionMode::Fixed, secs(100), 0.0);
+        assert!((0..10).all(|_| exact.next_interval() == secs(100)));
+    }
+
+    #[test]
+    fn intervals_are_never_shorter_than_a_second() {
+        let mut schedule = Schedule::new(EmissionMode::Fixed, Duration::ZERO, 1.0);
+        assert!((0..10).all(|_| schedule.next_interval() >= TRICKLE_MIN_INTERVAL));
+    }
+
+    #[test]
+    fn huge_period_is_clamped() {
+        let mut schedule = Schedule::new(EmissionMode::Fixed, Duration::MAX, 1.0);
+        assert!((0..10).all(|_| schedule.next_interval() <= MAX_PERIOD * 2));
+
+        let mut exact = Schedule::new(EmissionMode::Fixed, Duration::MAX, 0.0);
+        assert_eq!(exact.next_interval(), MAX_PERIOD);
+    }
+
+    #[test]
+    fn trickle_doubles_up_to_period() {
+        let mut schedule = Schedule::new(EmissionMode::Trickle, secs(10), 0.0);
+        let intervals: Vec<_> = (0..6).map(|_| schedule.next_interval()).collect();
+
+        assert_eq!(intervals, [1, 2, 4, 8, 10, 10].map(secs));
+    }
+
+    #[test]
+    fn trickle_reset_restarts_from_shortest_interval() {
+        let mut schedule = Schedule::new(EmissionMode::Trickle, secs(10), 0.0);
+
+        // Already at shortest interval
+        assert!(!schedule.reset());
+
+        schedule.next_interval();
+        schedule.next_interval();
+        assert!(schedule.reset());
+        assert_eq!(schedule.next_interval(), TRICKLE_MIN_INTERVAL);
+        assert_eq!(schedule.next_interval(), secs(2));
+
+        let mut fixed = Schedule::new(EmissionMode::Fixed, secs(10), 0.0);
+        assert!(!fixed.reset());
+    }
+}
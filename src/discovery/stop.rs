@@ -0,0 +1,42 @@
+use std::{sync::{Condvar, Mutex}, time::{Duration, Instant}};
+
+/// Stop signal shared by the tasks of a discovery session
+/// Tasks waiting on it are woken up as soon as it is raised
+#[derive(Debug, Default)]
+pub struct StopSignal {
+    stopped: Mutex<bool>,
+    condvar: Condvar
+}
+
+impl StopSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raise signal and wake waiting tasks up
+    pub fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Wait for `duration` or until signal is raised
+    /// Returns true if signal is raised
+    pub fn wait(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut stopped = self.stopped.lock().unwrap();
+
+        while !*stopped {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            stopped = self.condvar.wait_timeout(stopped, remaining).unwrap().0;
+        }
+
+        *stopped
+    }
+}
@@ -0,0 +1,207 @@
+use std::{collections::HashMap, fs::OpenOptions, io::{self, Write}, path::{Path, PathBuf}};
+
+use ed25519_dalek::VerifyingKey;
+
+use crate::{beacon::{signature::take_signature, Beacon, BeaconError, NodeIdentifier}, util::{decode_hex, encode_hex, parse_lines}};
+
+/// Public keys allowed to sign beacons of each node
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    keys: HashMap<NodeIdentifier, Vec<VerifyingKey>>,
+
+    /// Trust key of unknown nodes on first signed beacon
+    pub trust_on_first_use: bool,
+
+    /// Keys trusted on first use are appended to this file
+    path: Option<PathBuf>
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a trust store from a file, one `NODE_ID HEX_PUBLIC_KEY` per line, a node can have several keys
+    /// Keys trusted on first use are saved to this file
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let mut store = Self::new();
+
+        let keys = parse_lines(path, "trust store entry", |line| line.split_once(char::is_whitespace)
+            .and_then(|(node_id, key)| Some((node_id.to_owned(), parse_key(key.trim())?))))?;
+
+        for (node_id, key) in keys {
+            store.trust(node_id, key);
+        }
+
+        store.path = Some(path.to_owned());
+
+        Ok(store)
+    }
+
+    /// Allow `key` to sign beacons of `node_id`
+    pub fn trust(&mut self, node_id: NodeIdentifier, key: VerifyingKey) {
+        let keys = self.keys.entry(node_id).or_default();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    /// Check signature of `beacon` against keys pinned for its node id
+    /// Returns beacon without its signature service
+    pub fn verify(&mut self, mut beacon: Beacon) -> Result<Beacon, BeaconError> {
+        let Some(node_id) = beacon.node_id.clone() else {
+            return Err(BeaconError::Unauthenticated);
+        };
+
+        let (signed, signature) = take_signature(&mut beacon)?;
+
+        let verifies = |key: &VerifyingKey| key.verify_strict(&signed, &signature.signature).is_ok();
+
+        match self.keys.get(&node_id) {
+            Some(keys) => {
+                // Key ID selects pinned key, every pinned key is tried without it
+                let valid = match &signature.key_id {
+                    Some(key_id) => keys.contains(key_id) && verifies(key_id),
+                    None => keys.iter().any(verifies),
+                };
+
+                if valid { Ok(beacon) } else { Err(BeaconError::BadAuthentication) }
+            },
+            None => match signature.key_id {
+                // Pinned node id is written to trust store file, it must not break its lines
+                Some(key) if self.trust_on_first_use && !node_id.contains(|c: char| c.is_whitespace() || c.is_control()) => {
+                    if !verifies(&key) {
+                        return Err(BeaconError::BadAuthentication);
+                    }
+
+                    println!("Trusting key {} of {} on first use", encode_hex(key.as_bytes()), node_id);
+                    self.pin(node_id, key);
+                    Ok(beacon)
+                },
+                _ => Err(BeaconError::UntrustedNode),
+            }
+        }
+    }
+
+    fn pin(&mut self, node_id: NodeIdentifier, key: VerifyingKey) {
+        if let Some(path) = &self.path {
+            let saved = OpenOptions::new()
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{} {}", node_id, encode_hex(key.as_bytes())));
+
+            if let Err(e) = saved {
+                println!("Unable to save key of {} to {} : {}", node_id, path.display(), e);
+            }
+        }
+
+        self.trust(node_id, key);
+    }
+}
+
+fn parse_key(hex: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = decode_hex(hex)?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ed25519_dalek::SigningKey;
+
+    use crate::beacon::{signature::BeaconSigner, Beacon, BeaconError};
+    use crate::testing::{beacon, temp_file, NODE_ID};
+
+    use super::TrustStore;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn signed(seed: u8) -> Beacon {
+        BeaconSigner::new(key(seed)).sign(&beacon(1)).unwrap()
+    }
+
+    fn signed_by(node_id: &str) -> Beacon {
+        let mut beacon = beacon(1);
+        beacon.node_id = Some(node_id.into());
+        BeaconSigner::new(key(1)).sign(&beacon).unwrap()
+    }
+
+    #[test]
+    fn verifies_pinned_key() {
+        let mut store = TrustStore::new();
+        store.trust(NODE_ID.into(), key(1).verifying_key());
+
+        let received = Beacon::parse(&signed(1).as_bytes().unwrap()).unwrap();
+        let verified = store.verify(received).unwrap();
+
+        assert!(verified.services.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_key_and_tampered_beacon() {
+        let mut store = TrustStore::new();
+        store.trust(NODE_ID.into(), key(1).verifying_key());
+
+        assert!(matches!(store.verify(signed(2)), Err(BeaconError::BadAuthentication)));
+
+        let mut tampered = signed(1);
+        tampered.sequence_number += 1;
+        assert!(matches!(store.verify(tampered), Err(BeaconError::BadAuthentication)));
+    }
+
+    #[test]
+    fn rejects_unknown_node_and_unsigned_beacon() {
+        let mut store = TrustStore::new();
+        assert!(matches!(store.verify(signed(1)), Err(BeaconError::UntrustedNode)));
+
+        store.trust(NODE_ID.into(), key(1).verifying_key());
+        assert!(matches!(store.verify(beacon(1)), Err(BeaconError::Unauthenticated)));
+    }
+
+    #[test]
+    fn trusts_first_key_only() {
+        let mut store = TrustStore::new();
+        store.trust_on_first_use = true;
+
+        assert!(store.verify(signed(1)).is_ok());
+        assert!(store.verify(signed(1)).is_ok());
+        assert!(matches!(store.verify(signed(2)), Err(BeaconError::BadAuthentication)));
+    }
+
+    #[test]
+    fn pinned_key_is_saved() {
+        let path = temp_file("trust-store");
+        fs::write(&path, "").unwrap();
+
+        let mut store = TrustStore::from_file(&path).unwrap();
+        store.trust_on_first_use = true;
+        assert!(store.verify(signed(1)).is_ok());
+
+        let mut reloaded = TrustStore::from_file(&path).unwrap();
+        assert!(reloaded.verify(signed(1)).is_ok());
+        assert!(matches!(reloaded.verify(signed(2)), Err(BeaconError::BadAuthentication)));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn does_not_pin_node_id_breaking_trust_store() {
+        let path = temp_file("hostile-trust-store");
+        fs::write(&path, "").unwrap();
+
+        let mut store = TrustStore::from_file(&path).unwrap();
+        store.trust_on_first_use = true;
+
+        for node_id in ["dtn://a b/", "dtn://a\ndtn://b/", "dtn://a\t/", "dtn://a\u{7f}/"] {
+            assert!(matches!(store.verify(signed_by(node_id)), Err(BeaconError::UntrustedNode)));
+        }
+
+        assert!(TrustStore::from_file(&path).is_ok());
+        assert!(fs::read_to_string(&path).unwrap().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+}
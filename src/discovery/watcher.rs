@@ -0,0 +1,132 @@
+use std::{io, net::SocketAddr, os::fd::{AsRawFd, OwnedFd}, sync::{mpsc::Sender, Arc}, time::Duration};
+
+use nix::{errno::Errno, libc, sys::{socket::{bind, recv, setsockopt, socket, sockopt::ReceiveTimeout, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType}, time::{TimeVal, TimeValLike}}};
+
+use super::{announcer::solicit_on, interfaces::{list_interfaces, Interface}, join_groups, leave_groups, schedule::ScheduleReset, stop::StopSignal, uses_default_interface, wake, DiscoveryConfig, SharedBeacon, SharedInterfaces, SharedSocket};
+
+/// Longest time watcher waits for a change before checking stop signal
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// A change of network interfaces
+#[derive(Debug, Clone)]
+pub enum InterfaceChange {
+    /// Interface appeared, came up or its addresses changed
+    Up(Interface),
+
+    /// Interface disappeared, went down or lost its addresses
+    Down(Interface)
+}
+
+/// Rtnetlink socket notified of link and address changes
+struct ChangeNotifier {
+    socket: OwnedFd
+}
+
+impl ChangeNotifier {
+    fn new() -> io::Result<Self> {
+        let socket = socket(AddressFamily::Netlink, SockType::Raw, SockFlag::SOCK_CLOEXEC, SockProtocol::NetlinkRoute)?;
+
+        let groups = libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR;
+        bind(socket.as_raw_fd(), &NetlinkAddr::new(0, groups as u32))?;
+
+        setsockopt(&socket, ReceiveTimeout, &TimeVal::seconds(MAX_WAIT.as_secs() as i64))?;
+
+        Ok(Self { socket })
+    }
+
+    /// Wait up to `MAX_WAIT` for a change, returns false on timeout
+    fn wait(&self) -> io::Result<bool> {
+        let mut buf = [0_u8; 8192];
+
+        match recv(self.socket.as_raw_fd(), &mut buf, MsgFlags::empty()) {
+            Ok(_) => {},
+            Err(Errno::EAGAIN) => return Ok(false),
+            // Notifications were dropped, interfaces are listed again anyway
+            Err(Errno::ENOBUFS) => {},
+            Err(e) => return Err(e.into()),
+        }
+
+        // Changes come in bursts, a single listing covers them
+        while recv(self.socket.as_raw_fd(), &mut buf, MsgFlags::MSG_DONTWAIT).is_ok() {}
+
+        Ok(true)
+    }
+}
+
+/// Follow interfaces changes (rtnetlink on Linux)
+/// Multicast groups are joined and left accordingly, announcer and receiver are notified
+/// A solicitation is sent on interfaces coming up
+#[allow(clippy::too_many_arguments)]
+pub fn watcher_task(
+    config: DiscoveryConfig,
+    stop_signal: Arc<StopSignal>,
+    socket: SharedSocket,
+    interfaces: SharedInterfaces,
+    changes: Sender<InterfaceChange>,
+    wake_addr: SocketAddr,
+    reset: Arc<ScheduleReset>,
+    emitted_beacon: SharedBeacon
+) {
+    let notifier = match ChangeNotifier::new() {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            println!("Unable to watch network interfaces changes : {}", e);
+            return;
+        }
+    };
+
+    while !stop_signal.is_stopped() {
+        match notifier.wait() {
+            Ok(true) => {},
+            Ok(false) => continue,
+            Err(e) => {
+                println!("Error watching network interfaces : {}", e);
+                stop_signal.wait(MAX_WAIT);
+                continue;
+            }
+        }
+
+        let current = match list_interfaces(&config.interfaces) {
+            Ok(current) => current,
+            Err(e) => {
+                println!("Unable to list network interfaces : {}", e);
+                continue;
+            }
+        };
+
+        let previous = std::mem::replace(&mut *interfaces.write().unwrap(), current.clone());
+        let socket = socket.lock().unwrap();
+
+        // Default interface is only used while no interface is known
+        if uses_default_interface(&config, &previous) && !current.is_empty() {
+            leave_groups(&config, &socket, None);
+        }
+
+        for interface in previous.iter().filter(|it| !current.iter().any(|c| c.name == it.name)) {
+            println!("Interface {} down", interface.name);
+            leave_groups(&config, &socket, Some(interface));
+            let _ = changes.send(InterfaceChange::Down(interface.clone()));
+        }
+
+        for interface in current.iter().filter(|it| !previous.contains(it)) {
+            // Addresses changed, membership is renewed with new addresses
+            if let Some(old) = previous.iter().find(|it| it.name == interface.name) {
+                leave_groups(&config, &socket, Some(old));
+            }
+
+            println!("Interface {} up", interface.name);
+            join_groups(&config, &socket, Some(interface));
+            if !config.passive {
+                solicit_on(&config, &socket, interface, &emitted_beacon.read().unwrap());
+            }
+            reset.raise();
+            let _ = changes.send(InterfaceChange::Up(interface.clone()));
+        }
+
+        if uses_default_interface(&config, &current) && !previous.is_empty() {
+            join_groups(&config, &socket, None);
+        }
+
+        wake(wake_addr);
+    }
+}
@@ -0,0 +1,28 @@
+//! Archipel IP Beacon
+//!
+//! Neighbour discovery protocol implementation for Archipel Core.
+//!
+//! This crate exposes the beacon codec ([`Beacon`], [`Service`]) and the
+//! discovery engine ([`start_discovery`]) so other Archipel components can
+//! embed neighbour discovery. See `doc/0-protocol-specs.md` for the
+//! protocol itself.
+#![forbid(unsafe_code)]
+
+mod beacon;
+mod discovery;
+mod util;
+#[cfg(test)]
+mod testing;
+
+pub use beacon::auth::SharedKeys;
+pub use beacon::envelope::GroupKeys;
+pub use beacon::signature::BeaconSigner;
+pub use beacon::{flags, Beacon, BeaconError, NodeIdentifier, NotClaError, Service, MAX_BEACON_SIZE};
+pub use discovery::{start_announcing, start_discovery, DiscoveryConfig, DiscoveryHandle, DiscoveryStopper, IpConfig};
+pub use discovery::{DEFAULT_MULTICAST_V4, DEFAULT_MULTICAST_V6, DEFAULT_PORT};
+pub use discovery::{AddressPolicy, FamilyPreference, ScopePreference};
+pub use discovery::{ClaKind, ClaPolicy};
+pub use discovery::{EidPattern, InterfaceFilter, NodeFilter};
+pub use discovery::{RateLimit, ReceiveLimits, DEFAULT_MAX_BEACON_SIZE};
+pub use discovery::{Neighbour, NeighbourEvent, NeighbourState};
+pub use discovery::{EmissionMode, TrustStore};
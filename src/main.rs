@@ -1,17 +1,15 @@
-use std::net::IpAddr;
-use std::{net::SocketAddr, path::PathBuf};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{fs::File, net::SocketAddr, path::PathBuf};
 use std::time::Duration;
 use std::str::FromStr;
-use beacon::Beacon;
-use clap::Parser;
-use discovery::start_discovery;
+use archipel_ipbeacon::{start_discovery, AddressPolicy, Beacon, ClaKind, ClaPolicy, DiscoveryConfig, EmissionMode, FamilyPreference, IpConfig, ScopePreference, Service, SharedKeys, BeaconSigner, GroupKeys, TrustStore};
+use archipel_ipbeacon::{EidPattern, InterfaceFilter, NodeFilter, RateLimit, ReceiveLimits, DEFAULT_MAX_BEACON_SIZE, DEFAULT_MULTICAST_V4, DEFAULT_MULTICAST_V6, DEFAULT_PORT};
+use clap::{ArgGroup, Parser};
 use ud3tn_aap::{Agent, BaseAgent};
 
-mod beacon;
-mod discovery;
-
 #[derive(Debug, Parser)]
 #[command(about="Start ipndv8 daemon", long_about = None)]
+#[command(group(ArgGroup::new("authentication").args(["auth_keys", "trust_store", "group_keys"]).multiple(true)))]
 struct CLIArgs {
     /// Log more
     #[arg(short, long)]
@@ -21,9 +19,17 @@ struct CLIArgs {
     #[arg(short, long="socket", default_value="/run/archipel-core/archipel-core.socket")]
     socket_path: PathBuf,
 
-    /// Duration in seconds between two advertizments
-    #[arg(short, long="period", value_name="DURATION", default_value="30")]
+    /// Duration in seconds between two advertizments (longest one in trickle mode), at most a day
+    #[arg(short, long="period", value_name="DURATION", default_value="30", value_parser=clap::value_parser!(u64).range(1..=86400))]
     period_secs: u64,
+
+    /// How intervals between advertizments are chosen
+    #[arg(long, value_name="MODE", default_value="fixed", ignore_case=true)]
+    emission: EmissionMode,
+
+    /// Random variation of intervals between advertizments, in percent
+    #[arg(long, value_name="PERCENT", default_value="10", value_parser=clap::value_parser!(u8).range(0..=100))]
+    jitter: u8,
     
     /// Add a TCPCLv4 convergence layer to advertizments
     #[arg(long, value_name="PORT")]
@@ -57,16 +63,126 @@ struct CLIArgs {
     #[arg(short, long)]
     broadcast: bool,
 
-    /// Send additionnal unicast beacons to pre-defined ip addresses
-    #[arg(short = 'D', long, value_name="IP ADDRESS")]
-    direct: Vec<IpAddr>
-}
+    /// Also broadcast to 255.255.255.255 in addition to subnets broadcast addresses
+    #[arg(long, requires="broadcast")]
+    limited_broadcast: bool,
+
+    /// Send additionnal unicast beacons to pre-defined ip addresses (destination port if no port given)
+    #[arg(short = 'D', long, value_name="IP[:PORT]")]
+    direct: Vec<String>,
+
+    /// Port beacons are received on
+    #[arg(long, value_name="PORT", default_value_t=DEFAULT_PORT)]
+    port: u16,
+
+    /// Port beacons are emitted to (listen port by default)
+    #[arg(long, value_name="PORT")]
+    destination_port: Option<u16>,
+
+    /// Ipv4 multicast group
+    #[arg(long, value_name="ADDRESS", default_value_t=DEFAULT_MULTICAST_V4)]
+    multicast_v4: Ipv4Addr,
+
+    /// Ipv6 multicast group
+    #[arg(long, value_name="ADDRESS", default_value_t=DEFAULT_MULTICAST_V6)]
+    multicast_v6: Ipv6Addr,
+
+    /// Only emit and listen on this interface (can be repeated, `wlan*` matches every interface starting with wlan)
+    #[arg(short, long="interface", value_name="NAME")]
+    interfaces: Vec<String>,
+
+    /// Never emit and listen on this interface (can be repeated)
+    #[arg(long="exclude-interface", value_name="NAME")]
+    excluded_interfaces: Vec<String>,
+
+    /// Reject received beacons with unknown flags or extra fields
+    #[arg(long)]
+    strict: bool,
+
+    /// Only configure contacts to these node ids (can be repeated, `*` matches anything, `dtn://*.archipel.net/`, `ipn:100.*`)
+    #[arg(long, value_name="PATTERN")]
+    allow_node: Vec<EidPattern>,
+
+    /// Never configure contacts to these node ids (can be repeated)
+    #[arg(long, value_name="PATTERN")]
+    deny_node: Vec<EidPattern>,
+
+    /// Load node id filter from a file (`allow PATTERN` or `deny PATTERN` per line)
+    #[arg(long, value_name="FILE")]
+    node_filter: Option<PathBuf>,
+
+    /// Only listen and configure contacts, never emit beacons
+    #[arg(long, conflicts_with="announce_only")]
+    passive: bool,
+
+    /// Only emit beacons, never configure contacts in core
+    #[arg(long)]
+    announce_only: bool,
+
+    /// Node id to advertize, core is not contacted at all (announce-only mode)
+    #[arg(long, value_name="EID", requires="announce_only")]
+    node_id: Option<String>,
+
+    /// Sign beacons and only accept beacons signed with one of these shared keys (one hex key per line, first one signs)
+    #[arg(long, value_name="FILE")]
+    auth_keys: Option<PathBuf>,
+
+    /// Sign beacons with this Ed25519 secret key (32 hex encoded bytes)
+    #[arg(long, value_name="FILE")]
+    signing_key: Option<PathBuf>,
+
+    /// Only accept beacons signed by keys trusted for their node (`NODE_ID HEX_PUBLIC_KEY` per line)
+    #[arg(long, value_name="FILE")]
+    trust_store: Option<PathBuf>,
+
+    /// Trust key of unknown nodes on their first signed beacon (saved to trust store file if given)
+    #[arg(long)]
+    trust_on_first_use: bool,
+
+    /// Add emission time to beacons
+    #[arg(long)]
+    timestamp: bool,
+
+    /// Only accept beacons with a timestamp less than this many seconds from now, and newer than the last one of their node (implies --timestamp, needs --auth-keys, --trust-store or --group-keys)
+    #[arg(long, value_name="SECONDS", requires="authentication")]
+    replay_window: Option<u64>,
+
+    /// Encrypt beacons and only accept beacons encrypted with one of these group keys (`KEY_ID HEX_KEY` per line, first one encrypts)
+    #[arg(long, value_name="FILE")]
+    group_keys: Option<PathBuf>,
+
+    /// Largest datagram accepted as a beacon, in bytes
+    #[arg(long, value_name="BYTES", default_value_t=DEFAULT_MAX_BEACON_SIZE)]
+    max_beacon_size: usize,
+
+    /// Datagrams accepted per second from a single ip address
+    #[arg(long, value_name="RATE", default_value="5")]
+    source_rate: f64,
+
+    /// Beacons accepted per second from a single node id
+    #[arg(long, value_name="RATE", default_value="10")]
+    node_rate: f64,
 
-#[derive(Debug, Clone)]
-pub enum IpConfig {
-    Ipv4Only,
-    Ipv6Only,
-    Both
+    /// Config bundles sent per second to core
+    #[arg(long, value_name="RATE", default_value="10")]
+    config_rate: f64,
+
+    /// Preferred convergence layers of neighbours, most preferred first
+    #[arg(long, value_name="CLA,...", value_delimiter=',', ignore_case=true)]
+    cla_preference: Vec<ClaKind>,
+
+    /// Configure the first convergence layer of a neighbour accepting TCP connections instead of the preferred one
+    /// (core keeps a single CLA per node, it is probed again when contact is refreshed)
+    #[arg(long)]
+    cla_failover: bool,
+
+    /// Preferred address scope of neighbours heard from several addresses
+    #[arg(long, value_name="SCOPE", default_value="global", ignore_case=true)]
+    address_scope: ScopePreference,
+
+    /// Preferred IP family of neighbours heard on both
+    #[arg(long, value_name="FAMILY", default_value="any", ignore_case=true)]
+    address_family: FamilyPreference
 }
 
 fn main() {
@@ -76,12 +192,20 @@ fn main() {
     let args = CLIArgs::parse();
     let period = Duration::from_secs(args.period_secs);
     
-    let aap = Agent::connect_unix(&args.socket_path)
-        .expect("Unable to connect to Archipel core")
-        .register("ipbeacon".into())
-        .expect("Failed to register ipbeacon agent");
+    // Announce-only mode only needs core to know node id
+    let (node_id, aap) = match args.node_id {
+        Some(node_id) => (node_id, None),
+        None => {
+            let aap = Agent::connect_unix(&args.socket_path)
+                .expect("Unable to connect to Archipel core")
+                .register("ipbeacon".into())
+                .expect("Failed to register ipbeacon agent");
 
-    let node_id = aap.node_id().to_owned();
+            let node_id = aap.node_id().to_owned();
+
+            (node_id, (!args.announce_only).then_some(aap))
+        }
+    };
 
     let ip_config = if args.ipv4_only {
         IpConfig::Ipv4Only
@@ -91,10 +215,54 @@ fn main() {
         IpConfig::Both
     };
 
-    let extra_unicast: Vec<SocketAddr> = args.direct.clone()
-        .into_iter().map(|it| SocketAddr::new(it, 3005))
+    let destination_port = args.destination_port.unwrap_or(args.port);
+
+    let extra_unicast: Vec<SocketAddr> = args.direct.iter()
+        .map(|it| parse_direct(it, destination_port))
         .collect::<Vec<_>>();
 
+    let shared_keys = args.auth_keys.map(|path| SharedKeys::from_file(&path)
+        .unwrap_or_else(|e| panic!("Unable to read shared keys from {} : {}", path.display(), e)));
+
+    let signer = args.signing_key.map(|path| BeaconSigner::from_file(&path)
+        .unwrap_or_else(|e| panic!("Unable to read signing key from {} : {}", path.display(), e)));
+
+    if let Some(signer) = &signer {
+        println!("Signing beacons with key {}", signer.key_id());
+    }
+
+    let mut trust_store = match args.trust_store {
+        Some(path) => {
+            // Trust store is created on first use
+            if args.trust_on_first_use && !path.exists() {
+                File::create(&path)
+                    .unwrap_or_else(|e| panic!("Unable to create trust store {} : {}", path.display(), e));
+            }
+
+            Some(TrustStore::from_file(&path)
+                .unwrap_or_else(|e| panic!("Unable to read trust store from {} : {}", path.display(), e)))
+        },
+        None if args.trust_on_first_use => Some(TrustStore::new()),
+        None => None
+    };
+
+    if let Some(trust_store) = &mut trust_store {
+        trust_store.trust_on_first_use = args.trust_on_first_use;
+    }
+
+    let group_keys = args.group_keys.map(|path| GroupKeys::from_file(&path)
+        .unwrap_or_else(|e| panic!("Unable to read group keys from {} : {}", path.display(), e)));
+
+    let mut node_filter = NodeFilter {
+        allow: args.allow_node,
+        deny: args.deny_node
+    };
+
+    if let Some(path) = args.node_filter {
+        node_filter.load(&path)
+            .unwrap_or_else(|e| panic!("Unable to read node filter from {} : {}", path.display(), e));
+    }
+
     let mut base_beacon = Beacon::new();
     
     base_beacon.node_id = Some(node_id.clone());
@@ -102,15 +270,15 @@ fn main() {
     base_beacon.period = Some(period);
 
     if let Some(port) = args.tcpclv3 {
-        base_beacon.services.push(beacon::Service::TCPCLv3(port));
+        base_beacon.services.push(Service::TCPCLv3(port));
     }
 
     if let Some(port) = args.tcpclv4 {
-        base_beacon.services.push(beacon::Service::TCPCLv4(port));
+        base_beacon.services.push(Service::TCPCLv4(port));
     }
 
     if let Some(port) = args.mtcpcl {
-        base_beacon.services.push(beacon::Service::MTCPCL(port));
+        base_beacon.services.push(Service::MTCPCL(port));
     }
 
     if let Some(str) = args.geolocation {
@@ -119,29 +287,82 @@ fn main() {
                                 .map(|it| it.expect("Failed to parse location part"))
                                 .collect();
 
-        base_beacon.services.push(beacon::Service::GeoLocation(
+        base_beacon.services.push(Service::GeoLocation(
             *parts.first().expect("Missing latitude"),
             *parts.get(1).expect("Missing longitude")
         ));
     }
 
     if let Some(address) = args.address {
-        base_beacon.services.push(beacon::Service::Address(address));
+        base_beacon.services.push(Service::Address(address));
     }
 
     if args.verbose {
         println!("Base beacon advertizment : {:#?}", base_beacon);
     }
     
-    start_discovery(
-        args.verbose, 
+    let config = DiscoveryConfig {
+        verbose: args.verbose,
         ip_config,
-        args.broadcast,
-        base_beacon,
+        broadcast: args.broadcast,
+        limited_broadcast: args.limited_broadcast,
         period,
+        emission: args.emission,
+        jitter: f64::from(args.jitter) / 100.0,
+        extra_unicast,
+        listen_port: args.port,
+        destination_port,
+        multicast_v4: args.multicast_v4,
+        multicast_v6: args.multicast_v6,
+        interfaces: InterfaceFilter {
+            allow: args.interfaces,
+            deny: args.excluded_interfaces
+        },
+        strict: args.strict,
+        node_filter,
+        passive: args.passive,
+        limits: ReceiveLimits {
+            max_beacon_size: args.max_beacon_size,
+            per_source: RateLimit::per_second(args.source_rate),
+            per_node: RateLimit::per_second(args.node_rate),
+            config_bundles: RateLimit::per_second(args.config_rate)
+        },
+        shared_keys,
+        signer,
+        trust_store,
+        timestamp: args.timestamp || args.replay_window.is_some(),
+        replay_window: args.replay_window.map(Duration::from_secs),
+        group_keys,
+        events: None,
+        cla_policy: ClaPolicy {
+            preference: args.cla_preference,
+            failover: args.cla_failover
+        },
+        address_policy: AddressPolicy {
+            family: args.address_family,
+            scope: args.address_scope
+        }
+    };
+
+    let discovery = start_discovery(
+        config,
+        base_beacon,
         node_id,
-        aap,
-        extra_unicast
-    );
+        aap
+    ).unwrap_or_else(|e| panic!("Unable to start discovery on port {} : {}", args.port, e));
+
+    let stopper = discovery.stopper();
+    ctrlc::set_handler(move || {
+        println!("Shuttng down");
+        stopper.stop()
+    }).unwrap();
 
-}
\ No newline at end of file
+    discovery.join();
+}
+
+/// Parse a direct peer as `IP` or `IP:PORT` (`[IP]:PORT` for ipv6)
+fn parse_direct(target: &str, default_port: u16) -> SocketAddr {
+    SocketAddr::from_str(target)
+        .or_else(|_| IpAddr::from_str(target).map(|ip| SocketAddr::new(ip, default_port)))
+        .unwrap_or_else(|_| panic!("Invalid direct peer address {}", target))
+}
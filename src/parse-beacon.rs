@@ -1,7 +1,6 @@
 use std::io::Read;
 
-use beacon::Beacon;
-mod beacon;
+use archipel_ipbeacon::Beacon;
 
 fn main() {
     let mut buf = Vec::new();
@@ -0,0 +1,21 @@
+//! Fixtures shared by unit tests
+
+use std::{env, path::PathBuf, process};
+
+use crate::beacon::Beacon;
+
+/// Node ID of beacons built by [`beacon`]
+pub const NODE_ID: &str = "dtn://a/";
+
+/// Beacon of [`NODE_ID`] with the given sequence number
+pub fn beacon(sequence_number: u64) -> Beacon {
+    let mut beacon = Beacon::new();
+    beacon.node_id = Some(NODE_ID.into());
+    beacon.sequence_number = sequence_number;
+    beacon
+}
+
+/// Path of a file named after `name` in temporary directory, unique to this process
+pub fn temp_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("archipel-ipbeacon-{}-{}", process::id(), name))
+}
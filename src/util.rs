@@ -0,0 +1,48 @@
+use std::{fs, io, path::Path};
+
+/// Parse each line of file at `path` with `parse`, lines are trimmed
+/// Empty lines and lines starting with `#` are ignored
+/// Lines are not echoed in errors, key files hold secrets
+pub(crate) fn parse_lines<T>(path: &Path, what: &str, parse: impl Fn(&str) -> Option<T>) -> io::Result<Vec<T>> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| parse(line).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Invalid {} on line {} of {}", what, number, path.display()))))
+        .collect()
+}
+
+/// Decode an hex string, None if it is not valid hex
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_hex, encode_hex};
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(decode_hex("00ff10Ab"), Some(vec![0x00, 0xff, 0x10, 0xab]));
+        assert_eq!(encode_hex(&[0x00, 0xff, 0x10, 0xab]), "00ff10ab");
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("é0"), None);
+    }
+}